@@ -0,0 +1,80 @@
+//! `#[derive(Hash)]` for the `hashmap` crate's own `Hash` trait, for key types
+//! that don't implement `std::hash::Hash`. Types that do are already covered
+//! by the blanket impl in `hashmap`, so deriving both on one type conflicts.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::{Data, DeriveInput, Fields, parse_macro_input, parse_quote};
+
+#[proc_macro_derive(Hash)]
+pub fn derive_hash(input: TokenStream) -> TokenStream {
+    let mut input = parse_macro_input!(input as DeriveInput);
+
+    // every type parameter has to be hashable for the whole thing to be
+    for param in input.generics.type_params_mut() {
+        param.bounds.push(parse_quote!(::hashmap::Hash));
+    }
+
+    let body = match &input.data {
+        Data::Struct(data) => {
+            let accessors = data.fields.members().map(|member| quote!(&self.#member));
+            hash_all(accessors)
+        }
+        // there's no value to hash, `match *self {}` proves it to the compiler
+        Data::Enum(data) if data.variants.is_empty() => quote!(match *self {}),
+        Data::Enum(data) => {
+            let arms = data.variants.iter().enumerate().map(|(index, variant)| {
+                let ident = &variant.ident;
+                let bindings: Vec<_> = (0..variant.fields.len())
+                    .map(|field| format_ident!("__field_{field}"))
+                    .collect();
+                let pattern = match &variant.fields {
+                    Fields::Named(fields) => {
+                        let names = fields.named.iter().map(|field| &field.ident);
+                        quote!(Self::#ident { #(#names: #bindings),* })
+                    }
+                    Fields::Unnamed(_) => quote!(Self::#ident ( #(#bindings),* )),
+                    Fields::Unit => quote!(Self::#ident),
+                };
+                let fields = hash_all(bindings.iter().map(|binding| quote!(#binding)));
+                quote! {
+                    #pattern => {
+                        ::core::hash::Hasher::write_usize(state, #index);
+                        #fields
+                    }
+                }
+            });
+            quote! {
+                match self {
+                    #(#arms)*
+                }
+            }
+        }
+        Data::Union(data) => {
+            return syn::Error::new_spanned(
+                data.union_token,
+                "Hash can't be derived for unions, there's no telling which field is live",
+            )
+            .to_compile_error()
+            .into();
+        }
+    };
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    quote! {
+        impl #impl_generics ::hashmap::Hash for #name #ty_generics #where_clause {
+            fn hash<__H: ::core::hash::Hasher>(&self, state: &mut __H) {
+                #body
+            }
+        }
+    }
+    .into()
+}
+
+fn hash_all(fields: impl Iterator<Item = TokenStream2>) -> TokenStream2 {
+    quote! {
+        #( ::hashmap::Hash::hash(#fields, state); )*
+    }
+}
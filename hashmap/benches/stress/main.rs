@@ -0,0 +1,110 @@
+//! The `stress` test workload, run against the control byte `HashMap`, the
+//! quadratic probing map it replaced and `std::collections::HashMap`.
+
+use std::hint::black_box;
+
+use criterion::{BenchmarkId, Criterion, criterion_group, criterion_main};
+use quadratic::QuadraticMap;
+
+mod quadratic;
+
+const SIZES: [u16; 3] = [1 << 8, 1 << 12, u16::MAX];
+
+fn put(c: &mut Criterion) {
+    let mut group = c.benchmark_group("put");
+    for size in SIZES {
+        group.bench_with_input(
+            BenchmarkId::new("control_bytes", size),
+            &size,
+            |b, &size| {
+                b.iter(|| {
+                    let mut map = hashmap::HashMap::with_capacity(10);
+                    for i in 0..size {
+                        map.put(i, i);
+                    }
+                    map
+                })
+            },
+        );
+        group.bench_with_input(BenchmarkId::new("incremental", size), &size, |b, &size| {
+            b.iter(|| {
+                let mut map = hashmap::HashMap::with_capacity(10);
+                map.set_incremental_resize(true);
+                for i in 0..size {
+                    map.put(i, i);
+                }
+                map
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("quadratic", size), &size, |b, &size| {
+            b.iter(|| {
+                let mut map = QuadraticMap::with_capacity(10);
+                for i in 0..size {
+                    map.put(i, i);
+                }
+                map
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("std", size), &size, |b, &size| {
+            b.iter(|| {
+                let mut map = std::collections::HashMap::with_capacity(10);
+                for i in 0..size {
+                    map.insert(i, i);
+                }
+                map
+            })
+        });
+    }
+    group.finish();
+}
+
+fn get(c: &mut Criterion) {
+    let mut group = c.benchmark_group("get");
+    for size in SIZES {
+        let mut ours = hashmap::HashMap::with_capacity(10);
+        let mut quadratic = QuadraticMap::with_capacity(10);
+        let mut std = std::collections::HashMap::with_capacity(10);
+        // only even keys go in, so every other lookup misses. Wider than the
+        // sizes, so doubling the largest doesn't wrap around.
+        for i in 0..u32::from(size) {
+            ours.put(i * 2, i);
+            quadratic.put(i * 2, i);
+            std.insert(i * 2, i);
+        }
+        group.bench_with_input(
+            BenchmarkId::new("control_bytes", size),
+            &size,
+            |b, &size| {
+                b.iter(|| {
+                    (0..u32::from(size))
+                        .map(|i| ours.get(&i).copied())
+                        .for_each(|v| {
+                            black_box(v);
+                        })
+                })
+            },
+        );
+        group.bench_with_input(BenchmarkId::new("quadratic", size), &size, |b, &size| {
+            b.iter(|| {
+                (0..u32::from(size))
+                    .map(|i| quadratic.get(&i).copied())
+                    .for_each(|v| {
+                        black_box(v);
+                    })
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("std", size), &size, |b, &size| {
+            b.iter(|| {
+                (0..u32::from(size))
+                    .map(|i| std.get(&i).copied())
+                    .for_each(|v| {
+                        black_box(v);
+                    })
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, put, get);
+criterion_main!(benches);
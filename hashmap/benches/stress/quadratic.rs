@@ -0,0 +1,106 @@
+//! The map as it was before the control byte redesign: one `Slot` per entry,
+//! probed quadratically one slot at a time, comparing keys on every step.
+//! Only kept around so the benchmarks have something to compare against.
+
+use std::hash::{BuildHasher, Hash};
+
+use hashmap::RandomState;
+
+#[derive(Default)]
+enum Slot<K, V> {
+    #[default]
+    Empty,
+    Full(K, V),
+}
+
+pub struct QuadraticMap<K, V> {
+    bucket: Vec<Slot<K, V>>,
+    growth_remaining: usize,
+    current_size: usize,
+    hash_builder: RandomState,
+}
+
+impl<K: Hash + PartialEq, V> QuadraticMap<K, V> {
+    pub fn with_capacity(capacity: usize) -> Self {
+        let bucket_len = calc_cap(capacity);
+        Self {
+            bucket: bucket_with_capacity(bucket_len),
+            growth_remaining: calc_bucket_len(bucket_len - 1),
+            current_size: 0,
+            hash_builder: RandomState::new(),
+        }
+    }
+
+    fn probe_sequence(&self, hash: usize) -> impl Iterator<Item = usize> + use<K, V> {
+        let m = self.bucket.len();
+        (0..m).map(move |i| hash.wrapping_add((i * i + i) / 2) % m)
+    }
+
+    fn probe(&self, hash: usize, k: &K) -> usize {
+        for probe_index in self.probe_sequence(hash) {
+            match &self.bucket[probe_index] {
+                Slot::Full(k_inner, _) if !k.eq(k_inner) => continue,
+                _ => return probe_index,
+            }
+        }
+        unreachable!("We have an overflowing bucket")
+    }
+
+    pub fn get(&self, k: &K) -> Option<&V> {
+        let hash = self.hash_builder.hash_one(k) as usize;
+        match &self.bucket[self.probe(hash, k)] {
+            Slot::Full(_, v) => Some(v),
+            Slot::Empty => None,
+        }
+    }
+
+    pub fn put(&mut self, k: K, v: V) -> Option<V> {
+        if self.growth_remaining < 1 {
+            self.resize();
+        }
+        let index = self.probe(self.hash_builder.hash_one(&k) as usize, &k);
+        if let Slot::Empty = self.bucket[index] {
+            self.current_size += 1;
+            self.growth_remaining -= 1;
+        }
+        match std::mem::replace(&mut self.bucket[index], Slot::Full(k, v)) {
+            Slot::Full(_, v) => Some(v),
+            Slot::Empty => None,
+        }
+    }
+
+    fn resize(&mut self) {
+        let next_bucket_len = calc_cap(self.bucket.len() + 1);
+        let old = std::mem::replace(&mut self.bucket, bucket_with_capacity(next_bucket_len));
+        for slot in old {
+            if let Slot::Full(k, v) = slot {
+                let index = self.probe(self.hash_builder.hash_one(&k) as usize, &k);
+                self.bucket[index] = Slot::Full(k, v);
+            }
+        }
+        self.growth_remaining = calc_bucket_len(next_bucket_len - 1) - self.current_size;
+    }
+}
+
+fn bucket_with_capacity<K, V>(bucket_len: usize) -> Vec<Slot<K, V>> {
+    let mut bucket = Vec::with_capacity(bucket_len);
+    bucket.resize_with(bucket_len, Default::default);
+    bucket
+}
+
+fn calc_bucket_len(capacity: usize) -> usize {
+    if capacity < 8 {
+        capacity
+    } else {
+        (capacity + 1) / 8 * 7
+    }
+}
+
+fn calc_cap(capacity: usize) -> usize {
+    if capacity < 8 {
+        if capacity < 4 { 4 } else { 8 }
+    } else {
+        let adjusted = capacity * 8 / 7;
+        adjusted.next_power_of_two()
+    }
+}
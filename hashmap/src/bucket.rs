@@ -0,0 +1,313 @@
+#[cfg(feature = "alloc")]
+use alloc::{vec, vec::Vec};
+use core::{borrow::Borrow, marker::PhantomData};
+
+use crate::Slot;
+
+/// Number of control bytes matched against at once
+pub(crate) const GROUP_WIDTH: usize = 8;
+
+/// Control byte of a slot that was never used
+const EMPTY: u8 = 0b1111_1111;
+/// Control byte of a slot whose entry was removed, probing has to carry on past it
+const DELETED: u8 = 0b1000_0000;
+
+/// The top 7 bits of the hash, stored in the control byte of a full slot.
+/// The high bit is always clear, which is what tells full slots apart from
+/// `EMPTY` and `DELETED`.
+fn h2(hash: usize) -> u8 {
+    (hash >> (usize::BITS - 7)) as u8
+}
+
+fn repeat(byte: u8) -> u64 {
+    u64::from_ne_bytes([byte; GROUP_WIDTH])
+}
+
+/// `GROUP_WIDTH` control bytes packed into a word, matched all at once
+#[derive(Clone, Copy)]
+struct Group(u64);
+
+impl Group {
+    fn load(ctrl: &[u8], index: usize) -> Self {
+        Group(u64::from_le_bytes(
+            ctrl[index..index + GROUP_WIDTH].try_into().unwrap(),
+        ))
+    }
+
+    /// Bytes equal to `byte`. Can report a false positive right after a
+    /// real match, which is fine since the keys get compared anyway.
+    fn match_byte(self, byte: u8) -> BitMask {
+        let cmp = self.0 ^ repeat(byte);
+        BitMask(cmp.wrapping_sub(repeat(0x01)) & !cmp & repeat(0x80))
+    }
+
+    fn match_empty(self) -> BitMask {
+        // only EMPTY has both of the top two bits set
+        BitMask(self.0 & (self.0 << 1) & repeat(0x80))
+    }
+
+    fn match_empty_or_deleted(self) -> BitMask {
+        BitMask(self.0 & repeat(0x80))
+    }
+}
+
+/// One set high bit per matching byte of a [`Group`], iterates over their indices
+struct BitMask(u64);
+
+impl BitMask {
+    fn any_bit_set(&self) -> bool {
+        self.0 != 0
+    }
+
+    fn lowest_set_bit(&self) -> Option<usize> {
+        self.any_bit_set()
+            .then(|| self.0.trailing_zeros() as usize / 8)
+    }
+}
+
+impl Iterator for BitMask {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let bit = self.lowest_set_bit()?;
+        self.0 &= self.0 - 1;
+        Some(bit)
+    }
+}
+
+/// Triangular probing over whole groups, visits every group exactly once
+/// as long as the number of slots is a power of two
+struct ProbeSeq {
+    pos: usize,
+    stride: usize,
+}
+
+impl ProbeSeq {
+    fn move_next(&mut self, bucket_mask: usize) -> Option<()> {
+        self.stride += GROUP_WIDTH;
+        if self.stride > bucket_mask + GROUP_WIDTH {
+            return None;
+        }
+        self.pos = (self.pos + self.stride) & bucket_mask;
+        Some(())
+    }
+}
+
+/// Slots plus one control byte per slot, the first `GROUP_WIDTH` of which
+/// are repeated past the end so a group can be loaded from any position.
+/// Tables smaller than a group instead see `EMPTY` padding past their end.
+///
+/// A [`FixedHashMap`](crate::FixedHashMap) lends it borrowed slices, every
+/// other map owns its memory through [`Bucket`].
+#[derive(Debug, Clone)]
+pub(crate) struct RawBucket<K, V, Ctrl, Slots> {
+    pub(crate) ctrl: Ctrl,
+    pub(crate) slots: Slots,
+    marker: PhantomData<Slot<K, V>>,
+}
+
+/// A bucket that owns its memory
+#[cfg(feature = "alloc")]
+pub(crate) type Bucket<K, V> = RawBucket<K, V, Vec<u8>, Vec<Slot<K, V>>>;
+
+#[cfg(feature = "alloc")]
+impl<K, V> Bucket<K, V> {
+    /// This will never create a zero sized bucket
+    pub(crate) fn bucket_with_capacity(bucket_len: usize) -> Bucket<K, V> {
+        debug_assert!(bucket_len.is_power_of_two());
+        let mut slots = Vec::with_capacity(bucket_len);
+        slots.resize_with(bucket_len, Default::default);
+        Bucket {
+            ctrl: vec![EMPTY; bucket_len + GROUP_WIDTH],
+            slots,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<K, V, Ctrl, Slots> RawBucket<K, V, Ctrl, Slots>
+where
+    Ctrl: AsRef<[u8]> + AsMut<[u8]>,
+    Slots: AsRef<[Slot<K, V>]> + AsMut<[Slot<K, V>]>,
+{
+    /// Empties memory handed over by the caller and uses it as a bucket.
+    /// `slots` has to be a power of two long and `ctrl` `GROUP_WIDTH` longer.
+    pub(crate) fn from_parts(mut ctrl: Ctrl, mut slots: Slots) -> Self {
+        debug_assert!(slots.as_ref().len().is_power_of_two());
+        debug_assert_eq!(ctrl.as_ref().len(), slots.as_ref().len() + GROUP_WIDTH);
+        ctrl.as_mut().fill(EMPTY);
+        slots.as_mut().fill_with(Default::default);
+        RawBucket {
+            ctrl,
+            slots,
+            marker: PhantomData,
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.slots.as_ref().len()
+    }
+
+    fn bucket_mask(&self) -> usize {
+        self.len() - 1
+    }
+
+    fn probe_seq(&self, hash: usize) -> ProbeSeq {
+        ProbeSeq {
+            pos: hash & self.bucket_mask(),
+            stride: 0,
+        }
+    }
+
+    fn set_ctrl(&mut self, index: usize, ctrl: u8) {
+        let mirror = (index.wrapping_sub(GROUP_WIDTH) & self.bucket_mask()) + GROUP_WIDTH;
+        self.ctrl.as_mut()[index] = ctrl;
+        self.ctrl.as_mut()[mirror] = ctrl;
+    }
+
+    pub(crate) fn is_empty_slot(&self, index: usize) -> bool {
+        self.ctrl.as_ref()[index] == EMPTY
+    }
+
+    /// Index of the slot holding `k`, if there is one
+    pub(crate) fn find<Q>(&self, hash: usize, k: &Q) -> Option<usize>
+    where
+        K: Borrow<Q>,
+        Q: PartialEq + ?Sized,
+    {
+        self.find_with(hash, |k_inner, _| k.eq(k_inner.borrow()))
+    }
+
+    /// Index of the first slot on the probe path of `hash` whose entry `eq` accepts
+    pub(crate) fn find_with(
+        &self,
+        hash: usize,
+        mut eq: impl FnMut(&K, &V) -> bool,
+    ) -> Option<usize> {
+        let h2 = h2(hash);
+        let mut probe = self.probe_seq(hash);
+        loop {
+            let group = Group::load(self.ctrl.as_ref(), probe.pos);
+            for bit in group.match_byte(h2) {
+                let index = (probe.pos + bit) & self.bucket_mask();
+                if let Some((k_inner, v_inner)) = &self.slots.as_ref()[index]
+                    && eq(k_inner, v_inner)
+                {
+                    return Some(index);
+                }
+            }
+            if group.match_empty().any_bit_set() {
+                return None;
+            }
+            probe.move_next(self.bucket_mask())?;
+        }
+    }
+
+    /// First free slot on the probe path of `hash`, reusing tombstones
+    pub(crate) fn find_insert_slot(&self, hash: usize) -> usize {
+        let mut probe = self.probe_seq(hash);
+        loop {
+            let group = Group::load(self.ctrl.as_ref(), probe.pos);
+            if let Some(bit) = group.match_empty_or_deleted().lowest_set_bit() {
+                let index = (probe.pos + bit) & self.bucket_mask();
+                if self.is_full_slot(index) {
+                    // the padding of a small table wrapped onto a full slot,
+                    // the whole table fits in the first group so look there
+                    return Group::load(self.ctrl.as_ref(), 0)
+                        .match_empty_or_deleted()
+                        .lowest_set_bit()
+                        .expect("We have an overflowing bucket");
+                }
+                return index;
+            }
+            probe
+                .move_next(self.bucket_mask())
+                .expect("We have an overflowing bucket");
+        }
+    }
+
+    pub(crate) fn bucket_put(&mut self, k: K, v: V, hash: usize, index: usize) -> Option<V> {
+        self.set_ctrl(index, h2(hash));
+        let slot = self.slots.as_mut()[index].replace((k, v));
+        slot.map(|(_, v)| v)
+    }
+
+    /// Leaves a tombstone behind, so probe paths running through `index` stay intact
+    pub(crate) fn bucket_remove(&mut self, index: usize) -> Option<(K, V)> {
+        self.set_ctrl(index, DELETED);
+        self.slots.as_mut()[index].take()
+    }
+
+    /// How many groups `find` has to look at before reaching `index`,
+    /// starting from 1 for an entry in the very first group of its probe path
+    #[cfg(feature = "alloc")]
+    pub(crate) fn probe_length(&self, hash: usize, index: usize) -> usize {
+        let mut probe = self.probe_seq(hash);
+        let mut length = 1;
+        while index.wrapping_sub(probe.pos) & self.bucket_mask() >= GROUP_WIDTH
+            && probe.move_next(self.bucket_mask()).is_some()
+        {
+            length += 1;
+        }
+        length
+    }
+
+    pub(crate) fn is_deleted_slot(&self, index: usize) -> bool {
+        self.ctrl.as_ref()[index] == DELETED
+    }
+
+    /// Going by the control byte alone, which is what probing sees
+    fn is_full_slot(&self, index: usize) -> bool {
+        self.ctrl.as_ref()[index] & 0x80 == 0
+    }
+
+    /// Marks every slot as never used, without touching the entries themselves
+    pub(crate) fn clear_ctrl(&mut self) {
+        self.ctrl.as_mut().fill(EMPTY);
+    }
+
+    /// Turns every tombstone back into an empty slot by moving the entries
+    /// to where they would have gone without them, all within the bucket's
+    /// own memory. `hash` has to give the same hashes the entries were put
+    /// in with.
+    pub(crate) fn rehash_in_place(&mut self, hash: impl Fn(&K) -> usize) {
+        // DELETED now means "still has to be rehashed"
+        for index in 0..self.len() {
+            let ctrl = if self.is_full_slot(index) {
+                DELETED
+            } else {
+                EMPTY
+            };
+            self.set_ctrl(index, ctrl);
+        }
+        for index in 0..self.len() {
+            if !self.is_deleted_slot(index) {
+                continue;
+            }
+            loop {
+                let (k, _) = self.slots.as_ref()[index]
+                    .as_ref()
+                    .expect("a slot waiting to be rehashed has an entry");
+                let hash = hash(k);
+                let new_index = self.find_insert_slot(hash);
+                // staying within the same group of the probe path is as
+                // good as moving, so don't
+                let start = hash & self.bucket_mask();
+                let probe_group =
+                    |index: usize| (index.wrapping_sub(start) & self.bucket_mask()) / GROUP_WIDTH;
+                if probe_group(index) == probe_group(new_index) {
+                    self.set_ctrl(index, h2(hash));
+                    break;
+                }
+                let displaced = self.is_deleted_slot(new_index);
+                self.set_ctrl(new_index, h2(hash));
+                self.slots.as_mut().swap(index, new_index);
+                if !displaced {
+                    self.set_ctrl(index, EMPTY);
+                    break;
+                }
+                // the entry swapped in still has to find its own place
+            }
+        }
+    }
+}
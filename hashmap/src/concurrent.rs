@@ -0,0 +1,531 @@
+use std::{
+    borrow::Borrow,
+    hash::BuildHasher,
+    ops::{Deref, DerefMut},
+    sync::{RwLock, RwLockReadGuard, RwLockWriteGuard},
+};
+
+use crate::{Hash, HashMap, RandomState, make_hash};
+
+/// A map that can be shared between threads. Keys are spread over a power of
+/// two number of shards, each an independently locked [`HashMap`], so threads
+/// only contend when they touch the same shard.
+pub struct ConcurrentHashMap<K, V, S = RandomState>
+where
+    K: Hash + PartialEq,
+{
+    shards: Box<[RwLock<HashMap<K, V, S>>]>,
+    hash_builder: S,
+    shift: u32,
+}
+
+impl<K, V> ConcurrentHashMap<K, V, RandomState>
+where
+    K: Hash + PartialEq,
+{
+    pub fn new() -> Self {
+        Self::with_hasher(RandomState::new())
+    }
+
+    /// `shard_amount` is rounded up to a power of two
+    pub fn with_shard_amount(shard_amount: usize) -> Self {
+        Self::with_shard_amount_and_hasher(shard_amount, RandomState::new())
+    }
+}
+
+impl<K, V> Default for ConcurrentHashMap<K, V, RandomState>
+where
+    K: Hash + PartialEq,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K, V, S> ConcurrentHashMap<K, V, S>
+where
+    K: Hash + PartialEq,
+    S: BuildHasher + Clone,
+{
+    pub fn with_hasher(hash_builder: S) -> Self {
+        let parallelism = std::thread::available_parallelism().map_or(1, usize::from);
+        Self::with_shard_amount_and_hasher(parallelism * 4, hash_builder)
+    }
+
+    pub fn with_shard_amount_and_hasher(shard_amount: usize, hash_builder: S) -> Self {
+        let shard_amount = shard_amount.max(2).next_power_of_two();
+        let shards = (0..shard_amount)
+            .map(|_| RwLock::new(HashMap::with_hasher(hash_builder.clone())))
+            .collect();
+        Self {
+            shards,
+            hash_builder,
+            shift: usize::BITS - shard_amount.trailing_zeros(),
+        }
+    }
+
+    pub fn shard_amount(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// The top 7 bits end up in the control bytes of the shard itself, so
+    /// the shard is picked from the bits right below them
+    fn shard_index<Q: Hash + ?Sized>(&self, k: &Q) -> usize {
+        (make_hash(&self.hash_builder, k) << 7) >> self.shift
+    }
+
+    fn read_shard(&self, index: usize) -> RwLockReadGuard<'_, HashMap<K, V, S>> {
+        // a hash or comparison that panicked halfway through a resize may
+        // have left the shard in pieces, so there's no recovering from it
+        self.shards[index]
+            .read()
+            .expect("a shard was poisoned by a panicking thread")
+    }
+
+    fn write_shard(&self, index: usize) -> RwLockWriteGuard<'_, HashMap<K, V, S>> {
+        self.shards[index]
+            .write()
+            .expect("a shard was poisoned by a panicking thread")
+    }
+
+    /// Where `k` is in the bucket of `shard`. Shards never resize
+    /// incrementally, so there is no old bucket to look in as well.
+    fn find<Q>(shard: &HashMap<K, V, S>, k: &Q) -> Option<usize>
+    where
+        K: Borrow<Q>,
+        Q: Hash + PartialEq + ?Sized,
+    {
+        debug_assert!(!shard.is_resizing(), "shards never resize incrementally");
+        shard.bucket.find(make_hash(&shard.hash_builder, k), k)
+    }
+
+    /// The returned guard keeps the shard of `k` read locked until dropped
+    pub fn get<Q>(&self, k: &Q) -> Option<Ref<'_, K, V, S>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + PartialEq + ?Sized,
+    {
+        let shard = self.read_shard(self.shard_index(k));
+        let index = Self::find(&shard, k)?;
+        Some(Ref { shard, index })
+    }
+
+    /// The returned guard keeps the shard of `k` write locked until dropped
+    pub fn get_mut<Q>(&self, k: &Q) -> Option<RefMut<'_, K, V, S>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + PartialEq + ?Sized,
+    {
+        let shard = self.write_shard(self.shard_index(k));
+        let index = Self::find(&shard, k)?;
+        Some(RefMut { shard, index })
+    }
+
+    pub fn contains_key<Q>(&self, k: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + PartialEq + ?Sized,
+    {
+        self.read_shard(self.shard_index(k)).contains_key(k)
+    }
+
+    /// Returns the previous item if possible, otherwise None
+    pub fn put(&self, k: K, v: V) -> Option<V> {
+        self.write_shard(self.shard_index(&k)).put(k, v)
+    }
+
+    pub fn remove<Q>(&self, k: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + PartialEq + ?Sized,
+    {
+        self.write_shard(self.shard_index(k)).remove(k)
+    }
+
+    /// Write locks the shard of `k` for as long as the entry is around
+    pub fn entry(&self, k: K) -> Entry<'_, K, V, S> {
+        let shard = self.write_shard(self.shard_index(&k));
+        match Self::find(&shard, &k) {
+            Some(index) => Entry::Occupied(OccupiedEntry {
+                entry: RefMut { shard, index },
+            }),
+            None => Entry::Vacant(VacantEntry { shard, key: k }),
+        }
+    }
+
+    /// Only a hint while other threads are writing
+    pub fn len(&self) -> usize {
+        (0..self.shards.len())
+            .map(|index| self.read_shard(index).current_size)
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Read locks every shard at once, so the snapshot sees the map exactly as
+    /// it was at a single point in time. Writers block until it is dropped.
+    pub fn snapshot(&self) -> Snapshot<'_, K, V, S> {
+        // always locking in the same order keeps two snapshots from deadlocking
+        Snapshot {
+            shards: (0..self.shards.len())
+                .map(|index| self.read_shard(index))
+                .collect(),
+        }
+    }
+}
+
+pub struct Ref<'a, K, V, S>
+where
+    K: Hash + PartialEq,
+{
+    shard: RwLockReadGuard<'a, HashMap<K, V, S>>,
+    index: usize,
+}
+
+impl<K, V, S> Ref<'_, K, V, S>
+where
+    K: Hash + PartialEq,
+{
+    pub fn key(&self) -> &K {
+        self.pair().0
+    }
+
+    pub fn value(&self) -> &V {
+        self.pair().1
+    }
+
+    pub fn pair(&self) -> (&K, &V) {
+        let (k, v) = self.shard.bucket.slots[self.index]
+            .as_ref()
+            .expect("the shard is locked, so the slot can't have been emptied");
+        (k, v)
+    }
+}
+
+impl<K, V, S> Deref for Ref<'_, K, V, S>
+where
+    K: Hash + PartialEq,
+{
+    type Target = V;
+
+    fn deref(&self) -> &Self::Target {
+        self.value()
+    }
+}
+
+pub struct RefMut<'a, K, V, S>
+where
+    K: Hash + PartialEq,
+{
+    shard: RwLockWriteGuard<'a, HashMap<K, V, S>>,
+    index: usize,
+}
+
+impl<K, V, S> RefMut<'_, K, V, S>
+where
+    K: Hash + PartialEq,
+{
+    pub fn key(&self) -> &K {
+        self.pair().0
+    }
+
+    pub fn value(&self) -> &V {
+        self.pair().1
+    }
+
+    pub fn value_mut(&mut self) -> &mut V {
+        self.pair_mut().1
+    }
+
+    pub fn pair(&self) -> (&K, &V) {
+        let (k, v) = self.shard.bucket.slots[self.index]
+            .as_ref()
+            .expect("the shard is locked, so the slot can't have been emptied");
+        (k, v)
+    }
+
+    pub fn pair_mut(&mut self) -> (&K, &mut V) {
+        let (k, v) = self.shard.bucket.slots[self.index]
+            .as_mut()
+            .expect("the shard is locked, so the slot can't have been emptied");
+        (k, v)
+    }
+}
+
+impl<K, V, S> Deref for RefMut<'_, K, V, S>
+where
+    K: Hash + PartialEq,
+{
+    type Target = V;
+
+    fn deref(&self) -> &Self::Target {
+        self.value()
+    }
+}
+
+impl<K, V, S> DerefMut for RefMut<'_, K, V, S>
+where
+    K: Hash + PartialEq,
+{
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.value_mut()
+    }
+}
+
+pub enum Entry<'a, K, V, S>
+where
+    K: Hash + PartialEq,
+{
+    Occupied(OccupiedEntry<'a, K, V, S>),
+    Vacant(VacantEntry<'a, K, V, S>),
+}
+
+impl<'a, K, V, S> Entry<'a, K, V, S>
+where
+    K: Hash + PartialEq,
+    S: BuildHasher,
+{
+    pub fn key(&self) -> &K {
+        match self {
+            Entry::Occupied(entry) => entry.key(),
+            Entry::Vacant(entry) => entry.key(),
+        }
+    }
+
+    pub fn and_modify(mut self, f: impl FnOnce(&mut V)) -> Self {
+        if let Entry::Occupied(entry) = &mut self {
+            f(entry.get_mut());
+        }
+        self
+    }
+
+    pub fn or_insert(self, default: V) -> RefMut<'a, K, V, S> {
+        self.or_insert_with(|| default)
+    }
+
+    pub fn or_insert_with(self, default: impl FnOnce() -> V) -> RefMut<'a, K, V, S> {
+        match self {
+            Entry::Occupied(entry) => entry.into_ref(),
+            Entry::Vacant(entry) => entry.insert(default()),
+        }
+    }
+
+    pub fn or_default(self) -> RefMut<'a, K, V, S>
+    where
+        V: Default,
+    {
+        self.or_insert_with(V::default)
+    }
+}
+
+pub struct OccupiedEntry<'a, K, V, S>
+where
+    K: Hash + PartialEq,
+{
+    entry: RefMut<'a, K, V, S>,
+}
+
+impl<'a, K, V, S> OccupiedEntry<'a, K, V, S>
+where
+    K: Hash + PartialEq,
+    S: BuildHasher,
+{
+    pub fn key(&self) -> &K {
+        self.entry.key()
+    }
+
+    pub fn get(&self) -> &V {
+        self.entry.value()
+    }
+
+    pub fn get_mut(&mut self) -> &mut V {
+        self.entry.value_mut()
+    }
+
+    /// Returns the value that was replaced
+    pub fn insert(&mut self, v: V) -> V {
+        std::mem::replace(self.get_mut(), v)
+    }
+
+    pub fn into_ref(self) -> RefMut<'a, K, V, S> {
+        self.entry
+    }
+
+    pub fn remove(self) -> V {
+        self.remove_entry().1
+    }
+
+    pub fn remove_entry(self) -> (K, V) {
+        let RefMut { mut shard, index } = self.entry;
+        shard.current_size -= 1;
+        shard
+            .bucket
+            .bucket_remove(index)
+            .expect("the shard is locked, so the slot can't have been emptied")
+    }
+}
+
+pub struct VacantEntry<'a, K, V, S>
+where
+    K: Hash + PartialEq,
+{
+    shard: RwLockWriteGuard<'a, HashMap<K, V, S>>,
+    key: K,
+}
+
+impl<'a, K, V, S> VacantEntry<'a, K, V, S>
+where
+    K: Hash + PartialEq,
+    S: BuildHasher,
+{
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    pub fn into_key(self) -> K {
+        self.key
+    }
+
+    pub fn insert(self, v: V) -> RefMut<'a, K, V, S> {
+        let VacantEntry { mut shard, key } = self;
+        let hash = make_hash(&shard.hash_builder, &key);
+        let index = shard.insert_unique(hash, key, v);
+        RefMut { shard, index }
+    }
+}
+
+/// Every shard of a [`ConcurrentHashMap`] read locked at once
+pub struct Snapshot<'a, K, V, S>
+where
+    K: Hash + PartialEq,
+{
+    shards: Vec<RwLockReadGuard<'a, HashMap<K, V, S>>>,
+}
+
+impl<K, V, S> Snapshot<'_, K, V, S>
+where
+    K: Hash + PartialEq,
+{
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|shard| shard.current_size).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.shards.iter().flat_map(|shard| shard.iter())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Barrier, thread};
+
+    use super::*;
+
+    const THREADS: usize = 8;
+    const PER_THREAD: usize = 5_000;
+
+    #[test]
+    fn put_get_remove() {
+        let map = ConcurrentHashMap::with_shard_amount(3);
+        assert_eq!(map.shard_amount(), 4);
+        assert_eq!(map.put(String::from("Foo"), 1), None);
+        assert_eq!(map.put(String::from("Foo"), 2), Some(1));
+        assert_eq!(*map.get("Foo").unwrap(), 2);
+        *map.get_mut("Foo").unwrap() += 1;
+        assert_eq!(map.get("Foo").unwrap().pair(), (&String::from("Foo"), &3));
+        assert!(map.contains_key("Foo"));
+        assert_eq!(map.remove("Foo"), Some(3));
+        assert!(map.get("Foo").is_none());
+        assert!(map.is_empty());
+    }
+
+    #[test]
+    fn entry() {
+        let map = ConcurrentHashMap::new();
+        *map.entry("a").or_insert(0) += 1;
+        *map.entry("a").or_insert(0) += 1;
+        map.entry("b").and_modify(|v| *v += 10).or_insert(5);
+        map.entry("b").and_modify(|v| *v += 10).or_insert(5);
+        assert_eq!(*map.get("a").unwrap(), 2);
+        assert_eq!(*map.get("b").unwrap(), 15);
+        match map.entry("a") {
+            Entry::Occupied(entry) => assert_eq!(entry.remove_entry(), ("a", 2)),
+            Entry::Vacant(_) => unreachable!(),
+        }
+        assert_eq!(map.len(), 1);
+        assert_eq!(map.entry("a").key(), &"a");
+    }
+
+    #[test]
+    fn stress_disjoint_writers() {
+        let map = ConcurrentHashMap::new();
+        thread::scope(|s| {
+            for t in 0..THREADS {
+                let map = &map;
+                s.spawn(move || {
+                    for i in t * PER_THREAD..(t + 1) * PER_THREAD {
+                        assert_eq!(map.put(i, i), None);
+                    }
+                    for i in (t * PER_THREAD..(t + 1) * PER_THREAD).step_by(2) {
+                        assert_eq!(map.remove(&i), Some(i));
+                    }
+                });
+            }
+        });
+        assert_eq!(map.len(), THREADS * PER_THREAD / 2);
+        for i in 0..THREADS * PER_THREAD {
+            assert_eq!(map.get(&i).map(|v| *v), (i % 2 == 1).then_some(i));
+        }
+    }
+
+    #[test]
+    fn stress_shared_counters() {
+        let map = ConcurrentHashMap::with_shard_amount(4);
+        let barrier = Barrier::new(THREADS);
+        thread::scope(|s| {
+            for _ in 0..THREADS {
+                let (map, barrier) = (&map, &barrier);
+                s.spawn(move || {
+                    barrier.wait();
+                    for i in 0..PER_THREAD {
+                        *map.entry(i % 64).or_insert(0) += 1;
+                    }
+                });
+            }
+        });
+        let snapshot = map.snapshot();
+        assert_eq!(snapshot.len(), 64);
+        assert_eq!(
+            snapshot.iter().map(|(_, v)| v).sum::<usize>(),
+            THREADS * PER_THREAD
+        );
+    }
+
+    #[test]
+    fn snapshot_is_consistent() {
+        // keys go in one after the other, so any single point in time has
+        // seen exactly the first few of them
+        let map = ConcurrentHashMap::with_shard_amount(16);
+        thread::scope(|s| {
+            s.spawn(|| {
+                for i in 0..20_000usize {
+                    map.put(i, ());
+                }
+            });
+            s.spawn(|| {
+                for _ in 0..200 {
+                    let snapshot = map.snapshot();
+                    let len = snapshot.len();
+                    assert_eq!(snapshot.iter().count(), len);
+                    assert!(snapshot.iter().all(|(k, _)| *k < len));
+                }
+            });
+        });
+    }
+}
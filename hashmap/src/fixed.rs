@@ -0,0 +1,316 @@
+use core::{borrow::Borrow, fmt, hash::BuildHasher};
+
+use crate::{
+    DefaultHashBuilder, Hash, Iter, IterMut, Slot,
+    bucket::{GROUP_WIDTH, RawBucket},
+    calc_bucket_len, make_hash,
+};
+
+/// How many control bytes a [`FixedHashMap`] with `slots` slots needs
+pub const fn ctrl_len(slots: usize) -> usize {
+    slots + GROUP_WIDTH
+}
+
+/// Why a pair of buffers can't back a [`FixedHashMap`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BufferError {
+    /// The number of slots has to be a power of two, and at least 4
+    SlotCount(usize),
+    /// There has to be exactly [`ctrl_len`] control bytes for the slots
+    CtrlLen { expected: usize, found: usize },
+}
+
+impl fmt::Display for BufferError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::SlotCount(slots) => {
+                write!(f, "{slots} slots is not a power of two of at least 4")
+            }
+            Self::CtrlLen { expected, found } => {
+                write!(f, "expected {expected} control bytes, found {found}")
+            }
+        }
+    }
+}
+
+impl core::error::Error for BufferError {}
+
+/// A `put` that didn't fit, with the entry handed back
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CapacityError<K, V> {
+    pub key: K,
+    pub value: V,
+}
+
+impl<K, V> fmt::Display for CapacityError<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("the map is full")
+    }
+}
+
+impl<K: fmt::Debug, V: fmt::Debug> core::error::Error for CapacityError<K, V> {}
+
+/// A [`HashMap`](crate::HashMap) over memory the caller hands it, for when
+/// there is no allocator. It never grows, a `put` that doesn't fit fails.
+///
+/// Removed entries leave tombstones as usual. Once those are all that's
+/// left of the free space, the next `put` clears them out in place, which
+/// costs as much as a resize.
+pub struct FixedHashMap<'a, K, V, S = DefaultHashBuilder>
+where
+    K: Hash + PartialEq,
+{
+    bucket: RawBucket<K, V, &'a mut [u8], &'a mut [Slot<K, V>]>,
+    growth_remaining: usize,
+    current_size: usize,
+    hash_builder: S,
+}
+
+impl<'a, K, V> FixedHashMap<'a, K, V, DefaultHashBuilder>
+where
+    K: Hash + PartialEq,
+{
+    /// Takes over `slots` and `ctrl`, whatever is in them is dropped
+    pub fn with_buffer(
+        slots: &'a mut [Option<(K, V)>],
+        ctrl: &'a mut [u8],
+    ) -> Result<Self, BufferError> {
+        Self::with_buffer_and_hasher(slots, ctrl, DefaultHashBuilder::default())
+    }
+}
+
+impl<K, V, S> FixedHashMap<'_, K, V, S>
+where
+    K: Hash + PartialEq,
+{
+    pub fn len(&self) -> usize {
+        self.current_size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.current_size == 0
+    }
+
+    /// The most entries the map will ever hold
+    pub fn capacity(&self) -> usize {
+        calc_bucket_len(self.bucket.len() - 1)
+    }
+
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter::new(self.bucket.slots, self.current_size)
+    }
+
+    pub fn iter_mut(&mut self) -> IterMut<'_, K, V> {
+        IterMut::new(self.bucket.slots, self.current_size)
+    }
+
+    /// Drops every entry, the buffers stay borrowed
+    pub fn clear(&mut self) {
+        self.bucket.clear_ctrl();
+        self.bucket.slots.fill_with(|| None);
+        self.current_size = 0;
+        self.growth_remaining = self.capacity();
+    }
+}
+
+impl<'a, K, V, S> FixedHashMap<'a, K, V, S>
+where
+    K: Hash + PartialEq,
+    S: BuildHasher,
+{
+    /// Takes over `slots` and `ctrl`, whatever is in them is dropped.
+    /// `ctrl` has to be [`ctrl_len`] of `slots.len()` long.
+    pub fn with_buffer_and_hasher(
+        slots: &'a mut [Option<(K, V)>],
+        ctrl: &'a mut [u8],
+        hash_builder: S,
+    ) -> Result<Self, BufferError> {
+        if slots.len() < 4 || !slots.len().is_power_of_two() {
+            return Err(BufferError::SlotCount(slots.len()));
+        }
+        if ctrl.len() != ctrl_len(slots.len()) {
+            return Err(BufferError::CtrlLen {
+                expected: ctrl_len(slots.len()),
+                found: ctrl.len(),
+            });
+        }
+        let bucket = RawBucket::from_parts(ctrl, slots);
+        Ok(Self {
+            growth_remaining: calc_bucket_len(bucket.len() - 1),
+            bucket,
+            current_size: 0,
+            hash_builder,
+        })
+    }
+
+    pub fn get<Q>(&self, k: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + PartialEq + ?Sized,
+    {
+        let index = self.bucket.find(make_hash(&self.hash_builder, k), k)?;
+        self.bucket.slots[index].as_ref().map(|(_, v)| v)
+    }
+
+    pub fn get_mut<Q>(&mut self, k: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + PartialEq + ?Sized,
+    {
+        let index = self.bucket.find(make_hash(&self.hash_builder, k), k)?;
+        self.bucket.slots[index].as_mut().map(|(_, v)| v)
+    }
+
+    pub fn contains_key<Q>(&self, k: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + PartialEq + ?Sized,
+    {
+        self.bucket
+            .find(make_hash(&self.hash_builder, k), k)
+            .is_some()
+    }
+
+    /// Returns the previous item if possible, otherwise None. Fails if `k`
+    /// is new and the map already holds `capacity` entries.
+    pub fn put(&mut self, k: K, v: V) -> Result<Option<V>, CapacityError<K, V>> {
+        let hash = make_hash(&self.hash_builder, &k);
+        if let Some(index) = self.bucket.find(hash, &k) {
+            return Ok(self.bucket.bucket_put(k, v, hash, index));
+        }
+
+        if self.growth_remaining < 1 {
+            if self.current_size == self.capacity() {
+                return Err(CapacityError { key: k, value: v });
+            }
+            // the rest of the room is taken up by tombstones
+            self.bucket
+                .rehash_in_place(|k| make_hash(&self.hash_builder, k));
+            self.growth_remaining = self.capacity() - self.current_size;
+        }
+
+        let index = self.bucket.find_insert_slot(hash);
+        // tombstones were already paid for out of growth_remaining
+        if self.bucket.is_empty_slot(index) {
+            self.growth_remaining -= 1;
+        }
+        self.current_size += 1;
+        self.bucket.bucket_put(k, v, hash, index);
+        Ok(None)
+    }
+
+    /// Removes `k` from the map, returning its value if it was there.
+    /// The slot is left as a tombstone.
+    pub fn remove<Q>(&mut self, k: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + PartialEq + ?Sized,
+    {
+        let index = self.bucket.find(make_hash(&self.hash_builder, k), k)?;
+        self.current_size -= 1;
+        self.bucket.bucket_remove(index).map(|(_, v)| v)
+    }
+
+    pub fn hasher(&self) -> &S {
+        &self.hash_builder
+    }
+}
+
+impl<K, V, S> fmt::Debug for FixedHashMap<'_, K, V, S>
+where
+    K: Hash + PartialEq + fmt::Debug,
+    V: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl<'a, K: Hash + PartialEq, V, S> IntoIterator for &'a FixedHashMap<'_, K, V, S> {
+    type Item = (&'a K, &'a V);
+
+    type IntoIter = Iter<'a, K, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<'a, K: Hash + PartialEq, V, S> IntoIterator for &'a mut FixedHashMap<'_, K, V, S> {
+    type Item = (&'a K, &'a mut V);
+
+    type IntoIter = IterMut<'a, K, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter_mut()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FxBuildHasher;
+
+    #[test]
+    fn fills_up_and_fails() {
+        let mut slots: [Option<(u32, u32)>; 16] = Default::default();
+        let mut ctrl = [0; ctrl_len(16)];
+        let mut map =
+            FixedHashMap::with_buffer_and_hasher(&mut slots, &mut ctrl, FxBuildHasher::default())
+                .unwrap();
+        assert_eq!(map.capacity(), 14);
+        for i in 0..14 {
+            assert_eq!(map.put(i, i), Ok(None));
+        }
+        assert_eq!(map.put(3, 30), Ok(Some(3)));
+        assert_eq!(map.put(14, 14), Err(CapacityError { key: 14, value: 14 }));
+        for i in 0..14 {
+            assert_eq!(map.get(&i), Some(&if i == 3 { 30 } else { i }));
+        }
+        assert_eq!(map.iter().len(), 14);
+        map.clear();
+        assert!(map.is_empty());
+        assert_eq!(map.put(14, 14), Ok(None));
+    }
+
+    #[test]
+    fn tombstones_are_reclaimed() {
+        let mut slots: [Option<(u32, u32)>; 64] = [None; 64];
+        let mut ctrl = [0; ctrl_len(64)];
+        let mut map = FixedHashMap::with_buffer(&mut slots, &mut ctrl).unwrap();
+        let capacity = map.capacity() as u32;
+        // far more puts than slots, only possible if removals free up room
+        for i in 0..10 * capacity {
+            assert_eq!(map.put(i, i), Ok(None));
+            if i >= capacity / 2 {
+                assert_eq!(map.remove(&(i - capacity / 2)), Some(i - capacity / 2));
+            }
+        }
+        for i in 0..10 * capacity {
+            let expected = (i >= 19 * capacity / 2).then_some(i);
+            assert_eq!(map.get(&i).copied(), expected);
+        }
+        for (_, v) in &mut map {
+            *v += 1;
+        }
+        assert!(map.iter().all(|(k, v)| *v == k + 1));
+    }
+
+    #[test]
+    fn bad_buffers() {
+        let mut slots: [Option<(u8, u8)>; 12] = [None; 12];
+        let mut ctrl = [0; ctrl_len(12)];
+        assert_eq!(
+            FixedHashMap::with_buffer(&mut slots, &mut ctrl).err(),
+            Some(BufferError::SlotCount(12))
+        );
+        assert_eq!(
+            FixedHashMap::with_buffer(&mut slots[..8], &mut ctrl[..8]).err(),
+            Some(BufferError::CtrlLen {
+                expected: 16,
+                found: 8
+            })
+        );
+        assert!(FixedHashMap::with_buffer(&mut slots[..4], &mut ctrl[..12]).is_ok());
+    }
+}
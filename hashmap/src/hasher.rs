@@ -0,0 +1,239 @@
+#[cfg(feature = "std")]
+use core::hash::BuildHasher;
+use core::hash::{BuildHasherDefault, Hasher};
+
+/// SipHash with 1 compression and 3 finalization rounds, same as what std
+/// uses. Keyed, so an attacker who cannot see the keys cannot force collisions.
+#[derive(Debug, Clone)]
+pub struct SipHasher13 {
+    v0: u64,
+    v1: u64,
+    v2: u64,
+    v3: u64,
+    /// Bytes that did not fill up a whole word yet, little endian
+    tail: u64,
+    ntail: usize,
+    length: usize,
+}
+
+impl SipHasher13 {
+    pub fn new_with_keys(k0: u64, k1: u64) -> Self {
+        Self {
+            v0: k0 ^ 0x736f6d6570736575,
+            v1: k1 ^ 0x646f72616e646f6d,
+            v2: k0 ^ 0x6c7967656e657261,
+            v3: k1 ^ 0x7465646279746573,
+            tail: 0,
+            ntail: 0,
+            length: 0,
+        }
+    }
+
+    fn sip_round(&mut self) {
+        self.v0 = self.v0.wrapping_add(self.v1);
+        self.v1 = self.v1.rotate_left(13) ^ self.v0;
+        self.v0 = self.v0.rotate_left(32);
+        self.v2 = self.v2.wrapping_add(self.v3);
+        self.v3 = self.v3.rotate_left(16) ^ self.v2;
+        self.v0 = self.v0.wrapping_add(self.v3);
+        self.v3 = self.v3.rotate_left(21) ^ self.v0;
+        self.v2 = self.v2.wrapping_add(self.v1);
+        self.v1 = self.v1.rotate_left(17) ^ self.v2;
+        self.v2 = self.v2.rotate_left(32);
+    }
+
+    fn compress(&mut self, word: u64) {
+        self.v3 ^= word;
+        self.sip_round();
+        self.v0 ^= word;
+    }
+}
+
+impl Hasher for SipHasher13 {
+    fn write(&mut self, mut bytes: &[u8]) {
+        self.length += bytes.len();
+        if self.ntail != 0 {
+            let needed = (8 - self.ntail).min(bytes.len());
+            for (i, byte) in bytes[..needed].iter().enumerate() {
+                self.tail |= (*byte as u64) << (8 * (self.ntail + i));
+            }
+            self.ntail += needed;
+            bytes = &bytes[needed..];
+            if self.ntail < 8 {
+                return;
+            }
+            self.compress(self.tail);
+            self.tail = 0;
+            self.ntail = 0;
+        }
+        let (words, rest) = bytes.as_chunks::<8>();
+        for word in words {
+            self.compress(u64::from_le_bytes(*word));
+        }
+        for (i, byte) in rest.iter().enumerate() {
+            self.tail |= (*byte as u64) << (8 * i);
+        }
+        self.ntail = rest.len();
+    }
+
+    fn finish(&self) -> u64 {
+        let mut state = self.clone();
+        let last = ((self.length as u64 & 0xff) << 56) | self.tail;
+        state.compress(last);
+        state.v2 ^= 0xff;
+        for _ in 0..3 {
+            state.sip_round();
+        }
+        state.v0 ^ state.v1 ^ state.v2 ^ state.v3
+    }
+}
+
+/// What maps hash with unless told otherwise. Without `std` there is no
+/// randomness to key SipHash with, so it falls back to [`FxBuildHasher`].
+#[cfg(feature = "std")]
+pub type DefaultHashBuilder = RandomState;
+#[cfg(not(feature = "std"))]
+pub type DefaultHashBuilder = FxBuildHasher;
+
+/// Builds [`SipHasher13`]s with keys picked at random when the state is
+/// created, the default for [`HashMap`](crate::HashMap)
+#[cfg(feature = "std")]
+#[derive(Clone)]
+pub struct RandomState {
+    k0: u64,
+    k1: u64,
+}
+
+#[cfg(feature = "std")]
+impl RandomState {
+    pub fn new() -> Self {
+        // std already knows how to get randomness out of the OS, so borrow it
+        let seed = std::collections::hash_map::RandomState::new();
+        Self {
+            k0: seed.hash_one(0u8),
+            k1: seed.hash_one(1u8),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Default for RandomState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "std")]
+impl core::fmt::Debug for RandomState {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        // the keys are the whole point, don't leak them
+        f.debug_struct("RandomState").finish_non_exhaustive()
+    }
+}
+
+#[cfg(feature = "std")]
+impl BuildHasher for RandomState {
+    type Hasher = SipHasher13;
+
+    fn build_hasher(&self) -> Self::Hasher {
+        SipHasher13::new_with_keys(self.k0, self.k1)
+    }
+}
+
+const FX_SEED: u64 = 0x51_7c_c1_b7_27_22_0a_95;
+
+/// The multiply-rotate hash rustc uses internally. Much faster than
+/// [`SipHasher13`], but trivial to collide on purpose, so only use it for
+/// keys an attacker has no say in.
+#[derive(Debug, Clone, Default)]
+pub struct FxHasher {
+    hash: u64,
+}
+
+impl FxHasher {
+    fn add_to_hash(&mut self, word: u64) {
+        self.hash = (self.hash.rotate_left(5) ^ word).wrapping_mul(FX_SEED);
+    }
+}
+
+impl Hasher for FxHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        let (words, mut rest) = bytes.as_chunks::<8>();
+        for word in words {
+            self.add_to_hash(u64::from_le_bytes(*word));
+        }
+        if rest.len() >= 4 {
+            self.add_to_hash(u32::from_le_bytes(rest[..4].try_into().unwrap()) as u64);
+            rest = &rest[4..];
+        }
+        for byte in rest {
+            self.add_to_hash(*byte as u64);
+        }
+    }
+
+    fn write_u8(&mut self, i: u8) {
+        self.add_to_hash(i as u64);
+    }
+
+    fn write_u16(&mut self, i: u16) {
+        self.add_to_hash(i as u64);
+    }
+
+    fn write_u32(&mut self, i: u32) {
+        self.add_to_hash(i as u64);
+    }
+
+    fn write_u64(&mut self, i: u64) {
+        self.add_to_hash(i);
+    }
+
+    fn write_usize(&mut self, i: usize) {
+        self.add_to_hash(i as u64);
+    }
+
+    fn finish(&self) -> u64 {
+        self.hash
+    }
+}
+
+pub type FxBuildHasher = BuildHasherDefault<FxHasher>;
+
+#[cfg(test)]
+mod tests {
+    use core::hash::Hasher;
+
+    use super::*;
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn siphash_matches_std() {
+        // std's DefaultHasher is SipHash-1-3 with both keys zeroed
+        let inputs: [&[u8]; 5] = [b"", b"a", b"1234567", b"12345678", b"hello there, world"];
+        for input in inputs {
+            let mut ours = SipHasher13::new_with_keys(0, 0);
+            let mut theirs = std::hash::DefaultHasher::new();
+            ours.write(input);
+            theirs.write(input);
+            assert_eq!(ours.finish(), theirs.finish());
+        }
+    }
+
+    #[test]
+    fn siphash_split_writes() {
+        let mut whole = SipHasher13::new_with_keys(1, 2);
+        whole.write(b"the quick brown fox");
+        let mut split = SipHasher13::new_with_keys(1, 2);
+        split.write(b"the q");
+        split.write(b"uick br");
+        split.write(b"own fox");
+        assert_eq!(whole.finish(), split.finish());
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn random_state_is_random() {
+        let (a, b) = (RandomState::new(), RandomState::new());
+        assert_ne!(a.hash_one("key"), b.hash_one("key"));
+        assert_eq!(a.hash_one("key"), a.hash_one("key"));
+    }
+}
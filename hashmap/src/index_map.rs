@@ -0,0 +1,556 @@
+use alloc::{vec, vec::Vec};
+use core::{
+    borrow::Borrow, cmp::Ordering, hash::BuildHasher, iter::FusedIterator, ops::Index, slice,
+};
+
+use crate::{DefaultHashBuilder, Hash, bucket::Bucket, calc_bucket_len, calc_cap, make_hash};
+
+#[derive(Debug, Clone)]
+struct Item<K, V> {
+    /// Kept around so resizing and reordering never have to rehash
+    hash: usize,
+    key: K,
+    value: V,
+}
+
+/// A map that remembers the order its keys were first put in.
+///
+/// The entries live in a dense `Vec` in that order, while the control byte
+/// bucket only stores indices into it. Lookups cost the same as a
+/// [`HashMap`](crate::HashMap), iteration is as fast as walking a `Vec`.
+#[derive(Debug, Clone)]
+pub struct IndexMap<K, V, S = DefaultHashBuilder>
+where
+    K: Hash + PartialEq,
+{
+    entries: Vec<Item<K, V>>,
+    indices: Bucket<usize, ()>,
+    growth_remaining: usize,
+    hash_builder: S,
+}
+
+impl<K, V> IndexMap<K, V, DefaultHashBuilder>
+where
+    K: Hash + PartialEq,
+{
+    pub fn new() -> Self {
+        Self::with_hasher(DefaultHashBuilder::default())
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self::with_capacity_and_hasher(capacity, DefaultHashBuilder::default())
+    }
+}
+
+impl<K, V, S> IndexMap<K, V, S>
+where
+    K: Hash + PartialEq,
+{
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get_index(&self, index: usize) -> Option<(&K, &V)> {
+        self.entries.get(index).map(|item| (&item.key, &item.value))
+    }
+
+    pub fn get_index_mut(&mut self, index: usize) -> Option<(&K, &mut V)> {
+        self.entries
+            .get_mut(index)
+            .map(|item| (&item.key, &mut item.value))
+    }
+
+    pub fn first(&self) -> Option<(&K, &V)> {
+        self.get_index(0)
+    }
+
+    pub fn last(&self) -> Option<(&K, &V)> {
+        self.get_index(self.len().wrapping_sub(1))
+    }
+
+    /// Iterates in insertion order
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter(self.entries.iter())
+    }
+
+    pub fn iter_mut(&mut self) -> IterMut<'_, K, V> {
+        IterMut(self.entries.iter_mut())
+    }
+
+    pub fn keys(&self) -> impl DoubleEndedIterator<Item = &K> + ExactSizeIterator {
+        self.iter().map(|(k, _)| k)
+    }
+
+    pub fn values(&self) -> impl DoubleEndedIterator<Item = &V> + ExactSizeIterator {
+        self.iter().map(|(_, v)| v)
+    }
+
+    pub fn values_mut(&mut self) -> impl DoubleEndedIterator<Item = &mut V> + ExactSizeIterator {
+        self.iter_mut().map(|(_, v)| v)
+    }
+}
+
+impl<K, V, S> IndexMap<K, V, S>
+where
+    K: Hash + PartialEq,
+    S: BuildHasher,
+{
+    pub fn with_capacity_and_hasher(capacity: usize, hash_builder: S) -> Self {
+        let bucket_len = calc_cap(capacity);
+        Self {
+            entries: Vec::with_capacity(capacity),
+            indices: Bucket::bucket_with_capacity(bucket_len),
+            growth_remaining: calc_bucket_len(bucket_len - 1),
+            hash_builder,
+        }
+    }
+
+    pub fn with_hasher(hash_builder: S) -> Self {
+        Self::with_capacity_and_hasher(0, hash_builder)
+    }
+
+    pub fn hasher(&self) -> &S {
+        &self.hash_builder
+    }
+
+    /// The bucket slot and entry index of `k`
+    fn find<Q>(&self, k: &Q) -> Option<(usize, usize)>
+    where
+        K: Borrow<Q>,
+        Q: Hash + PartialEq + ?Sized,
+    {
+        let slot = self
+            .indices
+            .find_with(make_hash(&self.hash_builder, k), |index, _| {
+                k.eq(self.entries[*index].key.borrow())
+            })?;
+        Some((slot, self.indices.slots[slot]?.0))
+    }
+
+    /// The bucket slot pointing at the entry at `index`
+    fn find_slot_of(&self, index: usize) -> usize {
+        self.indices
+            .find_with(self.entries[index].hash, |i, _| *i == index)
+            .expect("every entry has a slot pointing at it")
+    }
+
+    pub fn get<Q>(&self, k: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + PartialEq + ?Sized,
+    {
+        let (_, index) = self.find(k)?;
+        Some(&self.entries[index].value)
+    }
+
+    pub fn get_mut<Q>(&mut self, k: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + PartialEq + ?Sized,
+    {
+        let (_, index) = self.find(k)?;
+        Some(&mut self.entries[index].value)
+    }
+
+    /// Position of `k` in insertion order
+    pub fn get_index_of<Q>(&self, k: &Q) -> Option<usize>
+    where
+        K: Borrow<Q>,
+        Q: Hash + PartialEq + ?Sized,
+    {
+        self.find(k).map(|(_, index)| index)
+    }
+
+    pub fn contains_key<Q>(&self, k: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + PartialEq + ?Sized,
+    {
+        self.find(k).is_some()
+    }
+
+    /// Returns the previous item if possible, otherwise None.
+    /// A key that is already in the map keeps its position.
+    pub fn put(&mut self, k: K, v: V) -> Option<V> {
+        self.put_full(k, v).1
+    }
+
+    /// Same as `put`, but also returns the position of `k`
+    pub fn put_full(&mut self, k: K, v: V) -> (usize, Option<V>) {
+        if let Some((_, index)) = self.find(&k) {
+            return (
+                index,
+                Some(core::mem::replace(&mut self.entries[index].value, v)),
+            );
+        }
+        let hash = make_hash(&self.hash_builder, &k);
+        let index = self.entries.len();
+        self.insert_index(hash, index);
+        self.entries.push(Item {
+            hash,
+            key: k,
+            value: v,
+        });
+        (index, None)
+    }
+
+    fn insert_index(&mut self, hash: usize, index: usize) {
+        if self.growth_remaining < 1 {
+            self.resize();
+        }
+        let slot = self.indices.find_insert_slot(hash);
+        // tombstones were already paid for out of growth_remaining
+        if self.indices.is_empty_slot(slot) {
+            self.growth_remaining -= 1;
+        }
+        self.indices.bucket_put(index, (), hash, slot);
+    }
+
+    fn resize(&mut self) {
+        let bucket_len = self.indices.len();
+        // mostly tombstones, so cleaning them out is enough to make room
+        let next_bucket_len = if self.len() < calc_bucket_len(bucket_len - 1) / 2 {
+            bucket_len
+        } else {
+            calc_cap(bucket_len + 1)
+        };
+        self.rebuild_indices(next_bucket_len);
+    }
+
+    /// Points a fresh bucket at every entry, in whatever order they're in now
+    fn rebuild_indices(&mut self, bucket_len: usize) {
+        self.indices = Bucket::bucket_with_capacity(bucket_len);
+        for (index, item) in self.entries.iter().enumerate() {
+            let slot = self.indices.find_insert_slot(item.hash);
+            self.indices.bucket_put(index, (), item.hash, slot);
+        }
+        self.growth_remaining = calc_bucket_len(bucket_len - 1) - self.entries.len();
+    }
+
+    /// Makes room for at least `additional` more entries without resizing
+    pub fn reserve(&mut self, additional: usize) {
+        self.entries.reserve(additional);
+        if additional > self.growth_remaining {
+            self.rebuild_indices(calc_cap(self.len() + additional));
+        }
+    }
+
+    /// Removes `k` by moving the last entry into its place. O(1), but
+    /// disturbs the order of that last entry.
+    pub fn swap_remove<Q>(&mut self, k: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + PartialEq + ?Sized,
+    {
+        let (slot, index) = self.find(k)?;
+        Some(self.swap_remove_found(slot, index).1)
+    }
+
+    pub fn swap_remove_index(&mut self, index: usize) -> Option<(K, V)> {
+        if index >= self.len() {
+            return None;
+        }
+        let slot = self.find_slot_of(index);
+        Some(self.swap_remove_found(slot, index))
+    }
+
+    fn swap_remove_found(&mut self, slot: usize, index: usize) -> (K, V) {
+        self.indices.bucket_remove(slot);
+        let last = self.entries.len() - 1;
+        if index != last {
+            let moved = self.find_slot_of(last);
+            self.indices.slots[moved] = Some((index, ()));
+        }
+        let item = self.entries.swap_remove(index);
+        (item.key, item.value)
+    }
+
+    /// Removes `k` and shifts every later entry down by one. Keeps the
+    /// order intact, but costs O(n).
+    pub fn shift_remove<Q>(&mut self, k: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + PartialEq + ?Sized,
+    {
+        let (slot, index) = self.find(k)?;
+        Some(self.shift_remove_found(slot, index).1)
+    }
+
+    pub fn shift_remove_index(&mut self, index: usize) -> Option<(K, V)> {
+        if index >= self.len() {
+            return None;
+        }
+        let slot = self.find_slot_of(index);
+        Some(self.shift_remove_found(slot, index))
+    }
+
+    fn shift_remove_found(&mut self, slot: usize, index: usize) -> (K, V) {
+        self.indices.bucket_remove(slot);
+        for (i, _) in self.indices.slots.iter_mut().flatten() {
+            if *i > index {
+                *i -= 1;
+            }
+        }
+        let item = self.entries.remove(index);
+        (item.key, item.value)
+    }
+
+    /// Removes the most recently inserted entry
+    pub fn pop(&mut self) -> Option<(K, V)> {
+        self.swap_remove_index(self.len().checked_sub(1)?)
+    }
+
+    pub fn sort_keys(&mut self)
+    where
+        K: Ord,
+    {
+        self.sort_by(|k1, _, k2, _| k1.cmp(k2));
+    }
+
+    /// Stable sort of the entries, the bucket gets rebuilt afterwards
+    pub fn sort_by(&mut self, mut compare: impl FnMut(&K, &V, &K, &V) -> Ordering) {
+        self.entries
+            .sort_by(|a, b| compare(&a.key, &a.value, &b.key, &b.value));
+        self.rebuild_indices(self.indices.len());
+    }
+}
+
+impl<K, V, S> Default for IndexMap<K, V, S>
+where
+    K: Hash + PartialEq,
+    S: BuildHasher + Default,
+{
+    fn default() -> Self {
+        Self::with_hasher(S::default())
+    }
+}
+
+impl<K, V, S> FromIterator<(K, V)> for IndexMap<K, V, S>
+where
+    K: Hash + PartialEq,
+    S: BuildHasher + Default,
+{
+    fn from_iter<T: IntoIterator<Item = (K, V)>>(iter: T) -> Self {
+        let mut map = Self::default();
+        map.extend(iter);
+        map
+    }
+}
+
+impl<K, V, S> Extend<(K, V)> for IndexMap<K, V, S>
+where
+    K: Hash + PartialEq,
+    S: BuildHasher,
+{
+    fn extend<T: IntoIterator<Item = (K, V)>>(&mut self, iter: T) {
+        for (k, v) in iter {
+            self.put(k, v);
+        }
+    }
+}
+
+impl<K, Q, V, S> Index<&Q> for IndexMap<K, V, S>
+where
+    K: Hash + PartialEq + Borrow<Q>,
+    Q: Hash + PartialEq + ?Sized,
+    S: BuildHasher,
+{
+    type Output = V;
+
+    /// Panics if `k` isn't in the map
+    fn index(&self, k: &Q) -> &Self::Output {
+        self.get(k).expect("key not found in IndexMap")
+    }
+}
+
+pub struct Iter<'a, K, V>(slice::Iter<'a, Item<K, V>>);
+
+impl<'a, K, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(|item| (&item.key, &item.value))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+impl<K, V> DoubleEndedIterator for Iter<'_, K, V> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.0.next_back().map(|item| (&item.key, &item.value))
+    }
+}
+
+pub struct IterMut<'a, K, V>(slice::IterMut<'a, Item<K, V>>);
+
+impl<'a, K, V> Iterator for IterMut<'a, K, V> {
+    type Item = (&'a K, &'a mut V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(|item| (&item.key, &mut item.value))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+impl<K, V> DoubleEndedIterator for IterMut<'_, K, V> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.0.next_back().map(|item| (&item.key, &mut item.value))
+    }
+}
+
+pub struct IntoIter<K, V>(vec::IntoIter<Item<K, V>>);
+
+impl<K, V> Iterator for IntoIter<K, V> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(|item| (item.key, item.value))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+impl<K, V> DoubleEndedIterator for IntoIter<K, V> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.0.next_back().map(|item| (item.key, item.value))
+    }
+}
+
+impl<K, V> ExactSizeIterator for Iter<'_, K, V> {}
+impl<K, V> ExactSizeIterator for IterMut<'_, K, V> {}
+impl<K, V> ExactSizeIterator for IntoIter<K, V> {}
+
+impl<K, V> FusedIterator for Iter<'_, K, V> {}
+impl<K, V> FusedIterator for IterMut<'_, K, V> {}
+impl<K, V> FusedIterator for IntoIter<K, V> {}
+
+impl<'a, K: Hash + PartialEq, V, S> IntoIterator for &'a IndexMap<K, V, S> {
+    type Item = (&'a K, &'a V);
+
+    type IntoIter = Iter<'a, K, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<'a, K: Hash + PartialEq, V, S> IntoIterator for &'a mut IndexMap<K, V, S> {
+    type Item = (&'a K, &'a mut V);
+
+    type IntoIter = IterMut<'a, K, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter_mut()
+    }
+}
+
+impl<K: Hash + PartialEq, V, S> IntoIterator for IndexMap<K, V, S> {
+    type Item = (K, V);
+
+    type IntoIter = IntoIter<K, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        IntoIter(self.entries.into_iter())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::String;
+
+    use super::*;
+
+    fn letters() -> IndexMap<char, usize> {
+        "qwertyuiop"
+            .chars()
+            .enumerate()
+            .map(|(i, c)| (c, i))
+            .collect()
+    }
+
+    #[test]
+    fn insertion_order() {
+        let mut map = letters();
+        assert_eq!(map.keys().collect::<String>(), "qwertyuiop");
+        assert_eq!(map.put('e', 100), Some(2));
+        assert_eq!(map.put_full('a', 10), (10, None));
+        assert_eq!(map.keys().collect::<String>(), "qwertyuiopa");
+        assert_eq!(map.get_index(2), Some((&'e', &100)));
+        assert_eq!(map.get_index_of(&'a'), Some(10));
+        assert_eq!(map[&'q'], 0);
+        assert_eq!(map.first(), Some((&'q', &0)));
+        assert_eq!(map.last(), Some((&'a', &10)));
+        assert_eq!(map.iter().next_back(), Some((&'a', &10)));
+        assert_eq!(map.get_index(11), None);
+    }
+
+    #[test]
+    fn swap_remove() {
+        let mut map = letters();
+        assert_eq!(map.swap_remove(&'w'), Some(1));
+        assert_eq!(map.swap_remove(&'w'), None);
+        assert_eq!(map.keys().collect::<String>(), "qpertyuio");
+        assert_eq!(map.get_index_of(&'p'), Some(1));
+        assert_eq!(map[&'p'], 9);
+        assert_eq!(map.swap_remove_index(8), Some(('o', 8)));
+        assert_eq!(map.pop(), Some(('i', 7)));
+        assert_eq!(map.keys().collect::<String>(), "qpertyu");
+        for (i, k) in "qpertyu".chars().enumerate() {
+            assert_eq!(map.get_index_of(&k), Some(i));
+        }
+    }
+
+    #[test]
+    fn shift_remove() {
+        let mut map = letters();
+        assert_eq!(map.shift_remove(&'w'), Some(1));
+        assert_eq!(map.shift_remove_index(0), Some(('q', 0)));
+        assert_eq!(map.keys().collect::<String>(), "ertyuiop");
+        for (i, k) in "ertyuiop".chars().enumerate() {
+            assert_eq!(map.get_index_of(&k), Some(i));
+        }
+        map.put('w', 1);
+        assert_eq!(map.get_index_of(&'w'), Some(8));
+    }
+
+    #[test]
+    fn sorting() {
+        let mut map = letters();
+        map.sort_keys();
+        assert_eq!(map.keys().collect::<String>(), "eiopqrtuwy");
+        for (i, k) in "eiopqrtuwy".chars().enumerate() {
+            assert_eq!(map.get_index_of(&k), Some(i));
+        }
+        map.sort_by(|_, v1, _, v2| v2.cmp(v1));
+        assert_eq!(map.keys().collect::<String>(), "poiuytrewq");
+        assert_eq!(map[&'y'], 5);
+    }
+
+    #[test]
+    fn churn() {
+        let mut map = IndexMap::new();
+        for i in 0..10_000usize {
+            map.put(i, i);
+            if i % 3 == 0 {
+                map.swap_remove(&(i / 2));
+            }
+        }
+        for (i, (k, v)) in map.iter().enumerate() {
+            assert_eq!(k, v);
+            assert_eq!(map.get_index_of(k), Some(i));
+        }
+        let owned: Vec<_> = map.clone().into_iter().collect();
+        assert_eq!(owned.len(), map.len());
+    }
+}
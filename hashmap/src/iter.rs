@@ -0,0 +1,311 @@
+#[cfg(feature = "alloc")]
+use alloc::vec;
+use core::{
+    iter::{Chain, FusedIterator},
+    slice,
+};
+
+use crate::Slot;
+#[cfg(feature = "alloc")]
+use crate::{Hash, HashMap, calc_bucket_len};
+
+#[cfg(feature = "alloc")]
+impl<K, V, S> HashMap<K, V, S>
+where
+    K: Hash + PartialEq,
+{
+    /// Iterates over every entry in bucket order, borrowing the map
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        let old = match &self.old {
+            Some(old) => &old.bucket.slots[old.next..],
+            None => &[],
+        };
+        Iter {
+            slots: self.bucket.slots.iter().chain(old),
+            remaining: self.current_size,
+        }
+    }
+
+    /// Same as `iter`, but the values can be modified in place
+    pub fn iter_mut(&mut self) -> IterMut<'_, K, V> {
+        let old = match &mut self.old {
+            Some(old) => &mut old.bucket.slots[old.next..],
+            None => &mut [],
+        };
+        IterMut {
+            slots: self.bucket.slots.iter_mut().chain(old),
+            remaining: self.current_size,
+        }
+    }
+
+    pub fn keys(&self) -> Keys<'_, K, V> {
+        Keys(self.iter())
+    }
+
+    pub fn values(&self) -> Values<'_, K, V> {
+        Values(self.iter())
+    }
+
+    pub fn values_mut(&mut self) -> ValuesMut<'_, K, V> {
+        ValuesMut(self.iter_mut())
+    }
+
+    /// Removes every entry while keeping the allocated bucket around.
+    /// The map is empty as soon as this is called, even if the returned
+    /// iterator is dropped before being exhausted.
+    pub fn drain(&mut self) -> Drain<'_, K, V> {
+        let remaining = core::mem::take(&mut self.current_size);
+        self.growth_remaining = calc_bucket_len(self.bucket.len() - 1);
+        self.bucket.clear_ctrl();
+        // an unfinished incremental resize is simply dropped along with its bucket
+        let old = self
+            .old
+            .take()
+            .map(|old| old.bucket.slots)
+            .unwrap_or_default();
+        Drain {
+            slots: self.bucket.slots.iter_mut(),
+            old: old.into_iter(),
+            remaining,
+        }
+    }
+}
+
+/// Slots of the bucket followed by those an incremental resize hasn't moved yet
+type BothBuckets<I> = Chain<I, I>;
+
+pub struct Iter<'a, K, V> {
+    slots: BothBuckets<slice::Iter<'a, Slot<K, V>>>,
+    remaining: usize,
+}
+
+impl<'a, K, V> Iter<'a, K, V> {
+    /// Over a single bucket that isn't part of a resize
+    pub(crate) fn new(slots: &'a [Slot<K, V>], remaining: usize) -> Self {
+        Self {
+            slots: slots.iter().chain(&[]),
+            remaining,
+        }
+    }
+}
+
+impl<'a, K, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (k, v) = self.slots.find_map(|slot| slot.as_ref())?;
+        self.remaining -= 1;
+        Some((k, v))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<K, V> Clone for Iter<'_, K, V> {
+    fn clone(&self) -> Self {
+        Self {
+            slots: self.slots.clone(),
+            remaining: self.remaining,
+        }
+    }
+}
+
+pub struct IterMut<'a, K, V> {
+    slots: BothBuckets<slice::IterMut<'a, Slot<K, V>>>,
+    remaining: usize,
+}
+
+impl<'a, K, V> IterMut<'a, K, V> {
+    /// Over a single bucket that isn't part of a resize
+    pub(crate) fn new(slots: &'a mut [Slot<K, V>], remaining: usize) -> Self {
+        Self {
+            slots: slots.iter_mut().chain(&mut []),
+            remaining,
+        }
+    }
+}
+
+impl<'a, K, V> Iterator for IterMut<'a, K, V> {
+    type Item = (&'a K, &'a mut V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (k, v) = self.slots.find_map(|slot| slot.as_mut())?;
+        self.remaining -= 1;
+        Some((&*k, v))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+#[cfg(feature = "alloc")]
+pub struct IntoIter<K, V> {
+    slots: BothBuckets<vec::IntoIter<Slot<K, V>>>,
+    remaining: usize,
+}
+
+#[cfg(feature = "alloc")]
+impl<K, V> Iterator for IntoIter<K, V> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let entry = self.slots.find_map(|slot| slot)?;
+        self.remaining -= 1;
+        Some(entry)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+#[cfg(feature = "alloc")]
+pub struct Drain<'a, K, V> {
+    slots: slice::IterMut<'a, Slot<K, V>>,
+    /// Whatever an incremental resize hadn't moved into the bucket yet
+    old: vec::IntoIter<Slot<K, V>>,
+    remaining: usize,
+}
+
+#[cfg(feature = "alloc")]
+impl<K, V> Iterator for Drain<'_, K, V> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let entry = self
+            .slots
+            .find_map(|slot| slot.take())
+            .or_else(|| self.old.find_map(|slot| slot))?;
+        self.remaining -= 1;
+        Some(entry)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<K, V> Drop for Drain<'_, K, V> {
+    fn drop(&mut self) {
+        // the map already thinks it is empty, so the slots have to follow
+        self.for_each(drop);
+    }
+}
+
+pub struct Keys<'a, K, V>(Iter<'a, K, V>);
+
+impl<'a, K, V> Iterator for Keys<'a, K, V> {
+    type Item = &'a K;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(|(k, _)| k)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+impl<K, V> Clone for Keys<'_, K, V> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+pub struct Values<'a, K, V>(Iter<'a, K, V>);
+
+impl<'a, K, V> Iterator for Values<'a, K, V> {
+    type Item = &'a V;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(|(_, v)| v)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+impl<K, V> Clone for Values<'_, K, V> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+pub struct ValuesMut<'a, K, V>(IterMut<'a, K, V>);
+
+impl<'a, K, V> Iterator for ValuesMut<'a, K, V> {
+    type Item = &'a mut V;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(|(_, v)| v)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+impl<K, V> ExactSizeIterator for Iter<'_, K, V> {}
+impl<K, V> ExactSizeIterator for IterMut<'_, K, V> {}
+#[cfg(feature = "alloc")]
+impl<K, V> ExactSizeIterator for IntoIter<K, V> {}
+#[cfg(feature = "alloc")]
+impl<K, V> ExactSizeIterator for Drain<'_, K, V> {}
+impl<K, V> ExactSizeIterator for Keys<'_, K, V> {}
+impl<K, V> ExactSizeIterator for Values<'_, K, V> {}
+impl<K, V> ExactSizeIterator for ValuesMut<'_, K, V> {}
+
+impl<K, V> FusedIterator for Iter<'_, K, V> {}
+impl<K, V> FusedIterator for IterMut<'_, K, V> {}
+#[cfg(feature = "alloc")]
+impl<K, V> FusedIterator for IntoIter<K, V> {}
+#[cfg(feature = "alloc")]
+impl<K, V> FusedIterator for Drain<'_, K, V> {}
+impl<K, V> FusedIterator for Keys<'_, K, V> {}
+impl<K, V> FusedIterator for Values<'_, K, V> {}
+impl<K, V> FusedIterator for ValuesMut<'_, K, V> {}
+
+#[cfg(feature = "alloc")]
+impl<'a, K: Hash + PartialEq, V, S> IntoIterator for &'a HashMap<K, V, S> {
+    type Item = (&'a K, &'a V);
+
+    type IntoIter = Iter<'a, K, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<'a, K: Hash + PartialEq, V, S> IntoIterator for &'a mut HashMap<K, V, S> {
+    type Item = (&'a K, &'a mut V);
+
+    type IntoIter = IterMut<'a, K, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter_mut()
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<K: Hash + PartialEq, V, S> IntoIterator for HashMap<K, V, S> {
+    type Item = (K, V);
+
+    type IntoIter = IntoIter<K, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        let old = self.old.map(|old| old.bucket.slots).unwrap_or_default();
+        IntoIter {
+            remaining: self.current_size,
+            slots: self.bucket.slots.into_iter().chain(old),
+        }
+    }
+}
@@ -0,0 +1,338 @@
+use alloc::boxed::Box;
+use core::{borrow::Borrow, hash::BuildHasher};
+
+use crate::{
+    DefaultHashBuilder, FxBuildHasher, Hash, HashMap,
+    lru::EvictionCallback,
+    slab::{List, NIL, Slab},
+};
+
+/// Keeps at most `capacity` entries, throwing out the one used the fewest
+/// times when a new one comes in. Ties go to the least recently used.
+///
+/// Entries with the same use count share a linked list, most recently used
+/// at the head, so counting a use is O(1), and so is the eviction a `put`
+/// makes. Taking out the last entry with the lowest count through `remove`
+/// or `pop_lfu` loses track of it though, and the next eviction after that
+/// is O(distinct counts) to find it again.
+pub struct LfuCache<K, V, S = DefaultHashBuilder>
+where
+    K: Hash + PartialEq,
+{
+    map: HashMap<K, usize, S>,
+    /// Key, value and use count
+    nodes: Slab<(K, V, usize)>,
+    /// Only counts with at least one entry have a list
+    frequencies: HashMap<usize, List, FxBuildHasher>,
+    /// Lowest count with a list, or stale after a removal until the next
+    /// new key or eviction
+    min_frequency: usize,
+    capacity: usize,
+    on_evict: Option<EvictionCallback<K, V>>,
+}
+
+impl<K, V> LfuCache<K, V, DefaultHashBuilder>
+where
+    K: Hash + PartialEq + Clone,
+{
+    pub fn new(capacity: usize) -> Self {
+        Self::with_hasher(capacity, DefaultHashBuilder::default())
+    }
+}
+
+impl<K, V, S> LfuCache<K, V, S>
+where
+    K: Hash + PartialEq + Clone,
+    S: BuildHasher,
+{
+    pub fn with_hasher(capacity: usize, hash_builder: S) -> Self {
+        Self {
+            map: HashMap::with_capacity_and_hasher(capacity, hash_builder),
+            nodes: Slab::with_capacity(capacity),
+            frequencies: HashMap::default(),
+            min_frequency: 0,
+            capacity,
+            on_evict: None,
+        }
+    }
+
+    pub fn set_eviction_callback(&mut self, on_evict: impl FnMut(K, V) + 'static) {
+        self.on_evict = Some(Box::new(on_evict));
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// How many times `k` was used since it was put in, counting the put
+    pub fn frequency<Q>(&self, k: &Q) -> Option<usize>
+    where
+        K: Borrow<Q>,
+        Q: Hash + PartialEq + ?Sized,
+    {
+        let index = *self.map.get(k)?;
+        Some(self.nodes.get(index).2)
+    }
+
+    fn link(&mut self, index: usize) {
+        let frequency = self.nodes.get(index).2;
+        match self.frequencies.get_mut(&frequency) {
+            Some(list) => self.nodes.push_front(list, index),
+            None => {
+                let mut list = List::default();
+                self.nodes.push_front(&mut list, index);
+                self.frequencies.put(frequency, list);
+            }
+        }
+    }
+
+    fn unlink(&mut self, index: usize) {
+        let frequency = self.nodes.get(index).2;
+        let list = self
+            .frequencies
+            .get_mut(&frequency)
+            .expect("every node is on the list of its frequency");
+        self.nodes.unlink(list, index);
+        if list.is_empty() {
+            self.frequencies.remove(&frequency);
+        }
+    }
+
+    fn touch(&mut self, index: usize) {
+        self.unlink(index);
+        let frequency = &mut self.nodes.get_mut(index).2;
+        if *frequency == self.min_frequency && !self.frequencies.contains_key(frequency) {
+            self.min_frequency += 1;
+        }
+        *frequency += 1;
+        self.link(index);
+    }
+
+    /// Counts as a use of `k`
+    pub fn get<Q>(&mut self, k: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + PartialEq + ?Sized,
+    {
+        let index = *self.map.get(k)?;
+        self.touch(index);
+        Some(&self.nodes.get(index).1)
+    }
+
+    /// Counts as a use of `k`
+    pub fn get_mut<Q>(&mut self, k: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + PartialEq + ?Sized,
+    {
+        let index = *self.map.get(k)?;
+        self.touch(index);
+        Some(&mut self.nodes.get_mut(index).1)
+    }
+
+    /// Looks at `k` without counting it as a use
+    pub fn peek<Q>(&self, k: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + PartialEq + ?Sized,
+    {
+        let index = *self.map.get(k)?;
+        Some(&self.nodes.get(index).1)
+    }
+
+    pub fn contains_key<Q>(&self, k: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + PartialEq + ?Sized,
+    {
+        self.map.contains_key(k)
+    }
+
+    /// Returns the previous item if possible, otherwise None. Replacing a
+    /// value counts as a use, a new key evicts the least frequently used
+    /// entry if the cache is full.
+    pub fn put(&mut self, k: K, v: V) -> Option<V> {
+        if let Some(&index) = self.map.get(&k) {
+            self.touch(index);
+            return Some(core::mem::replace(&mut self.nodes.get_mut(index).1, v));
+        }
+        if self.capacity == 0 {
+            self.evicted(k, v);
+            return None;
+        }
+        if self.len() >= self.capacity {
+            self.evict();
+        }
+        let index = self.nodes.insert((k.clone(), v, 1));
+        self.link(index);
+        self.map.put(k, index);
+        self.min_frequency = 1;
+        None
+    }
+
+    pub fn remove<Q>(&mut self, k: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + PartialEq + ?Sized,
+    {
+        let index = *self.map.get(k)?;
+        Some(self.remove_index(index).1)
+    }
+
+    /// Removes the entry that would be evicted next, without calling the
+    /// eviction callback
+    pub fn pop_lfu(&mut self) -> Option<(K, V)> {
+        if self.is_empty() {
+            return None;
+        }
+        if !self.frequencies.contains_key(&self.min_frequency) {
+            self.min_frequency = *self
+                .frequencies
+                .keys()
+                .min()
+                .expect("a non-empty cache has a frequency list");
+        }
+        let tail = self.frequencies[&self.min_frequency].tail;
+        debug_assert_ne!(tail, NIL);
+        Some(self.remove_index(tail))
+    }
+
+    fn remove_index(&mut self, index: usize) -> (K, V) {
+        self.unlink(index);
+        let ((k, v, _), moved) = self.nodes.remove(index);
+        self.map.remove(&k);
+        if let Some(from) = moved {
+            let (moved_key, _, frequency) = self.nodes.get(index);
+            self.frequencies
+                .get_mut(frequency)
+                .expect("every node is on the list of its frequency")
+                .relocate(from, index);
+            *self
+                .map
+                .get_mut(moved_key)
+                .expect("every node has a key in the map") = index;
+        }
+        (k, v)
+    }
+
+    fn evict(&mut self) {
+        if let Some((k, v)) = self.pop_lfu() {
+            self.evicted(k, v);
+        }
+    }
+
+    fn evicted(&mut self, k: K, v: V) {
+        if let Some(on_evict) = &mut self.on_evict {
+            on_evict(k, v);
+        }
+    }
+
+    /// Evicts the least frequently used entries until the cache fits in `capacity`
+    pub fn resize(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        while self.len() > capacity {
+            self.evict();
+        }
+    }
+
+    /// In no particular order
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.nodes.iter().map(|(k, v, _)| (k, v))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{rc::Rc, vec::Vec};
+    use core::cell::RefCell;
+
+    use super::LfuCache;
+
+    #[test]
+    fn evicts_least_frequently_used() {
+        let mut cache = LfuCache::new(3);
+        cache.put("a", 1);
+        cache.put("b", 2);
+        cache.put("c", 3);
+        cache.get("a");
+        cache.get("a");
+        cache.get("b");
+        assert_eq!(cache.frequency("a"), Some(3));
+        assert_eq!(cache.frequency("c"), Some(1));
+        cache.put("d", 4);
+        assert!(!cache.contains_key("c"));
+        // "d" is the only entry used once
+        cache.put("e", 5);
+        assert!(!cache.contains_key("d"));
+        // ties go to the least recently used
+        cache.get("e");
+        assert_eq!(cache.pop_lfu(), Some(("b", 2)));
+        assert_eq!(cache.pop_lfu(), Some(("e", 5)));
+        assert_eq!(cache.pop_lfu(), Some(("a", 1)));
+        assert_eq!(cache.pop_lfu(), None);
+    }
+
+    #[test]
+    fn min_frequency_after_remove() {
+        let mut cache = LfuCache::new(3);
+        cache.put(1, ());
+        cache.put(2, ());
+        for _ in 0..3 {
+            cache.get(&2);
+        }
+        cache.put(3, ());
+        cache.get(&3);
+        cache.remove(&1);
+        // nothing is left at frequency 1, the next eviction has to look further
+        cache.put(4, ());
+        cache.get(&4);
+        cache.get(&4);
+        cache.put(5, ());
+        assert!(!cache.contains_key(&3));
+        assert_eq!(cache.frequency(&4), Some(3));
+        assert_eq!(cache.len(), 3);
+    }
+
+    #[test]
+    fn eviction_callback_and_resize() {
+        let evicted = Rc::new(RefCell::new(Vec::new()));
+        let mut cache = LfuCache::new(3);
+        let sink = evicted.clone();
+        cache.set_eviction_callback(move |k, v| sink.borrow_mut().push((k, v)));
+        for i in 0..3 {
+            cache.put(i, i);
+            for _ in 0..i {
+                cache.get(&i);
+            }
+        }
+        cache.put(3, 3);
+        assert_eq!(*evicted.borrow(), [(0, 0)]);
+        cache.resize(1);
+        assert_eq!(*evicted.borrow(), [(0, 0), (3, 3), (1, 1)]);
+        assert_eq!(cache.iter().collect::<Vec<_>>(), [(&2, &2)]);
+    }
+
+    #[test]
+    fn churn() {
+        let mut cache = LfuCache::new(64);
+        for i in 0..10_000usize {
+            cache.put(i % 500, i);
+            if i % 5 == 0 {
+                cache.remove(&(i % 97));
+            }
+            cache.get(&(i % 13));
+        }
+        assert!(cache.len() <= 64);
+        for (k, v) in cache.iter() {
+            assert_eq!(cache.peek(k), Some(v));
+        }
+    }
+}
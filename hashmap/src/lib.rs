@@ -1,182 +1,490 @@
+//! Hash maps built on SwissTable style control bytes, plus the sets, caches
+//! and ordered maps on top of them.
+//!
+//! Works without `std` as long as there is an allocator, by turning off the
+//! default `std` feature and keeping `alloc`. That takes away
+//! [`RandomState`] and the [`ConcurrentHashMap`], and the maps default to
+//! [`FxBuildHasher`]. With no allocator at all, turning off `alloc` as well,
+//! only [`FixedHashMap`] is left, which lives in memory the caller hands it.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(feature = "alloc")]
+extern crate alloc;
+
+#[cfg(feature = "alloc")]
+use core::{borrow::Borrow, ops::Index};
+use core::hash::{BuildHasher, Hasher};
+
+#[cfg(feature = "alloc")]
+use bucket::Bucket;
+
+mod bucket;
+#[cfg(feature = "std")]
+pub mod concurrent;
+pub mod fixed;
+mod hasher;
+#[cfg(feature = "alloc")]
+pub mod index_map;
+mod iter;
+#[cfg(feature = "alloc")]
+pub mod lfu;
+#[cfg(feature = "alloc")]
+pub mod lru;
+#[cfg(feature = "alloc")]
+pub mod multi_map;
+#[cfg(feature = "alloc")]
+pub mod persistent;
+#[cfg(feature = "alloc")]
+pub mod set;
+#[cfg(feature = "alloc")]
+mod slab;
+#[cfg(feature = "alloc")]
+mod stats;
+
+#[cfg(feature = "std")]
+pub use concurrent::ConcurrentHashMap;
+pub use fixed::FixedHashMap;
+#[cfg(feature = "std")]
+pub use hasher::RandomState;
+pub use hasher::{DefaultHashBuilder, FxBuildHasher, FxHasher, SipHasher13};
+pub use hashmap_derive::Hash;
+#[cfg(feature = "alloc")]
+pub use index_map::IndexMap;
+#[cfg(feature = "alloc")]
+pub use iter::{Drain, IntoIter};
+pub use iter::{Iter, IterMut, Keys, Values, ValuesMut};
+#[cfg(feature = "alloc")]
+pub use lfu::LfuCache;
+#[cfg(feature = "alloc")]
+pub use lru::LruCache;
+#[cfg(feature = "alloc")]
+pub use multi_map::MultiMap;
+#[cfg(feature = "alloc")]
+pub use persistent::{PersistentHashMap, TransientHashMap};
+#[cfg(feature = "alloc")]
+pub use set::HashSet;
+#[cfg(feature = "alloc")]
+pub use stats::Stats;
+
+/// Keys feed themselves into a [`Hasher`], while the map decides which
+/// hasher that is through its [`BuildHasher`].
+///
+/// Every primitive and std type is covered by the impl for anything that
+/// implements [`std::hash::Hash`]. Types that don't can `#[derive(Hash)]`,
+/// as long as their fields are keys themselves.
 pub trait Hash {
-    fn hash(&self) -> usize;
+    fn hash<H: Hasher>(&self, state: &mut H);
+}
+
+/// Anything std already knows how to hash can be used as a key as is
+impl<T: core::hash::Hash + ?Sized> Hash for T {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        core::hash::Hash::hash(self, state)
+    }
+}
+
+fn make_hash<Q: Hash + ?Sized, S: BuildHasher>(hash_builder: &S, k: &Q) -> usize {
+    let mut state = hash_builder.build_hasher();
+    k.hash(&mut state);
+    state.finish() as usize
 }
 
 type Slot<K, V> = Option<(K, V)>;
 
-#[derive(Debug)]
-struct Bucket<K, V>(Vec<Slot<K, V>>)
+/// Slots of the bucket an incremental resize moves away from that each
+/// `put` and `remove` carries over to the new one
+#[cfg(feature = "alloc")]
+const MIGRATION_STEP: usize = 8;
+
+/// The bucket an incremental resize is moving entries out of
+#[cfg(feature = "alloc")]
+#[derive(Debug, Clone)]
+struct Migration<K, V> {
+    bucket: Bucket<K, V>,
+    /// Every slot before this one was already moved
+    next: usize,
+}
+
+#[cfg(feature = "alloc")]
+#[derive(Debug, Clone)]
+pub struct HashMap<K, V, S = DefaultHashBuilder>
 where
-    K: Hash + PartialEq;
+    K: Hash + PartialEq,
+{
+    bucket: Bucket<K, V>,
+    /// Entries not moved into `bucket` yet, during an incremental resize
+    old: Option<Migration<K, V>>,
+    incremental_resize: bool,
+    /// Includes the entries still in `old`
+    growth_remaining: usize,
+    current_size: usize,
+    hash_builder: S,
+}
 
-impl<K: std::ops::Deref, V: std::ops::Deref> std::ops::Deref for Bucket<K, V>
+#[cfg(feature = "alloc")]
+impl<K, V> HashMap<K, V, DefaultHashBuilder>
 where
     K: Hash + PartialEq,
 {
-    type Target = Vec<Slot<K, V>>;
+    pub fn new() -> Self {
+        Self::with_hasher(DefaultHashBuilder::default())
+    }
 
-    fn deref(&self) -> &Self::Target {
-        &self.0
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self::with_capacity_and_hasher(capacity, DefaultHashBuilder::default())
     }
 }
 
-#[derive(Debug)]
-pub struct HashMap<K, V>
+#[cfg(feature = "alloc")]
+impl<K, V, S> HashMap<K, V, S>
 where
     K: Hash + PartialEq,
 {
-    bucket: Bucket<K, V>,
-    growth_remaining: usize,
-    current_size: usize,
+    pub fn len(&self) -> usize {
+        self.current_size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.current_size == 0
+    }
+
+    /// How many entries fit before the next `put` has to resize
+    pub fn capacity(&self) -> usize {
+        self.current_size + self.growth_remaining
+    }
+
+    /// Whether an incremental resize still has entries left to move
+    pub fn is_resizing(&self) -> bool {
+        self.old.is_some()
+    }
 }
 
-impl<K, V> Bucket<K, V>
+#[cfg(feature = "alloc")]
+impl<K, V, S> HashMap<K, V, S>
 where
     K: Hash + PartialEq,
+    S: BuildHasher,
 {
-    fn probe(&self, k: &K) -> usize {
-        let m = self.0.len();
-        for i in 0..m {
-            let probe_index = (k.hash() + (i * i + i) / 2) % m;
-            if self.0[probe_index]
-                .as_ref()
-                .is_none_or(|(k_inner, _)| k.eq(k_inner))
-            {
-                return probe_index;
-            }
+    /// Entry holding `k`, in whichever bucket it currently lives
+    fn find_entry<Q>(&self, hash: usize, k: &Q) -> Option<&(K, V)>
+    where
+        K: Borrow<Q>,
+        Q: PartialEq + ?Sized,
+    {
+        if let Some(index) = self.bucket.find(hash, k) {
+            return self.bucket.slots[index].as_ref();
         }
-        unreachable!("We have an overflowing bucket")
+        let old = self.old.as_ref()?;
+        let index = old.bucket.find(hash, k)?;
+        old.bucket.slots[index].as_ref()
     }
-    /// This will never create a zero sized bucket
-    fn bucket_with_capacity(bucket_len: usize) -> Bucket<K, V> {
-        let mut bucket = Vec::with_capacity(bucket_len);
-        bucket.resize_with(bucket_len, Default::default);
-        Bucket(bucket)
+
+    fn find_entry_mut<Q>(&mut self, hash: usize, k: &Q) -> Option<&mut (K, V)>
+    where
+        K: Borrow<Q>,
+        Q: PartialEq + ?Sized,
+    {
+        if let Some(index) = self.bucket.find(hash, k) {
+            return self.bucket.slots[index].as_mut();
+        }
+        let old = self.old.as_mut()?;
+        let index = old.bucket.find(hash, k)?;
+        old.bucket.slots[index].as_mut()
     }
 
-    fn bucket_put(&mut self, k: K, v: V, index: usize) -> Option<V>
+    pub fn get<Q>(&self, k: &Q) -> Option<&V>
     where
-        K: Hash + PartialEq,
+        K: Borrow<Q>,
+        Q: Hash + PartialEq + ?Sized,
     {
-        let slot = Some((k, v));
+        self.find_entry(make_hash(&self.hash_builder, k), k)
+            .map(|(_, v)| v)
+    }
 
-        let slot = std::mem::replace(&mut self.0[index], slot);
-        slot.map(|(_, v)| v)
+    pub fn get_mut<Q>(&mut self, k: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + PartialEq + ?Sized,
+    {
+        self.find_entry_mut(make_hash(&self.hash_builder, k), k)
+            .map(|(_, v)| v)
     }
-}
-impl<K, V> HashMap<K, V>
-where
-    K: Hash + PartialEq + std::fmt::Debug,
-    V: std::fmt::Debug,
-{
-    pub fn get(&self, k: &K) -> Option<&V> {
-        if !self.bucket.0.is_empty() {
-            let index = self.bucket.probe(k);
-            self.bucket
-                .0
-                .get(index)
-                .and_then(|slot| slot.as_ref().map(|(_, v)| v))
-        } else {
-            None
-        }
+
+    pub fn contains_key<Q>(&self, k: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + PartialEq + ?Sized,
+    {
+        self.find_entry(make_hash(&self.hash_builder, k), k)
+            .is_some()
     }
 
     /// Returns the previous item if possible, otherwise None
     pub fn put(&mut self, k: K, v: V) -> Option<V> {
+        self.migrate(MIGRATION_STEP);
+        let hash = make_hash(&self.hash_builder, &k);
+        if let Some(entry) = self.find_entry_mut(hash, &k) {
+            let (_, v) = core::mem::replace(entry, (k, v));
+            return Some(v);
+        }
+
+        self.insert_unique(hash, k, v);
+        None
+    }
+
+    /// Inserts `k`, which the caller knows isn't in the map yet, returning
+    /// the slot it ended up in
+    fn insert_unique(&mut self, hash: usize, k: K, v: V) -> usize {
         if self.growth_remaining < 1 {
             self.resize();
         }
 
-        let index = self.bucket.probe(&k);
-        if self.bucket.0.get(index).is_some_and(|slot| slot.is_none()) {
-            self.current_size += 1;
+        let index = self.bucket.find_insert_slot(hash);
+        // tombstones were already paid for out of growth_remaining
+        if self.bucket.is_empty_slot(index) {
             self.growth_remaining -= 1;
         }
+        self.current_size += 1;
+
+        self.bucket.bucket_put(k, v, hash, index);
+        index
+    }
+
+    /// Removes `k` from the map, returning its value if it was there.
+    /// The slot is left as a tombstone until the next resize.
+    pub fn remove<Q>(&mut self, k: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + PartialEq + ?Sized,
+    {
+        self.migrate(MIGRATION_STEP);
+        let hash = make_hash(&self.hash_builder, k);
+        if let Some(index) = self.bucket.find(hash, k) {
+            self.current_size -= 1;
+            return self.bucket.bucket_remove(index).map(|(_, v)| v);
+        }
+        let old = self.old.as_mut()?;
+        let index = old.bucket.find(hash, k)?;
+        self.current_size -= 1;
+        // its slot in the new bucket was set aside when the resize started
+        self.growth_remaining += 1;
+        old.bucket.bucket_remove(index).map(|(_, v)| v)
+    }
+
+    /// Resizes by moving a few entries over on every `put` and `remove`
+    /// instead of all of them at once, so no single call pays for a whole
+    /// rehash. Lookups check both buckets while a resize is under way.
+    /// Turning it off finishes a resize that is under way.
+    pub fn set_incremental_resize(&mut self, incremental: bool) {
+        self.incremental_resize = incremental;
+        if !incremental {
+            self.migrate(usize::MAX);
+        }
+    }
 
-        self.bucket.bucket_put(k, v, index)
+    /// Moves the entries of up to `slots` slots out of the old bucket
+    fn migrate(&mut self, slots: usize) {
+        let Some(old) = &mut self.old else {
+            return;
+        };
+        let end = old.next.saturating_add(slots).min(old.bucket.len());
+        for slot in &mut old.bucket.slots[old.next..end] {
+            if let Some((k, v)) = slot.take() {
+                let hash = make_hash(&self.hash_builder, &k);
+                let index = self.bucket.find_insert_slot(hash);
+                self.bucket.bucket_put(k, v, hash, index);
+            }
+        }
+        old.next = end;
+        if end == old.bucket.len() {
+            self.old = None;
+        }
+    }
+
+    /// Makes room for at least `additional` more entries without resizing
+    pub fn reserve(&mut self, additional: usize) {
+        if additional > self.growth_remaining {
+            self.resize_to(calc_cap(self.current_size + additional));
+        }
+    }
+
+    /// Shrinks the bucket as far as it goes while still holding every entry,
+    /// clearing out tombstones along the way
+    pub fn shrink_to_fit(&mut self) {
+        let bucket_len = calc_cap(self.current_size);
+        let has_tombstones = self.capacity() < calc_bucket_len(self.bucket.len() - 1);
+        if bucket_len < self.bucket.len() || has_tombstones {
+            self.resize_to(bucket_len);
+        }
     }
+
     fn resize(&mut self) {
-        let next_bucket_len = calc_cap(self.bucket.0.len() + 1);
+        // the new bucket of an unfinished resize is the one that filled up
+        self.migrate(usize::MAX);
+        let bucket_len = self.bucket.len();
+        // mostly tombstones, so cleaning them out is enough to make room
+        let next_bucket_len = if self.current_size < calc_bucket_len(bucket_len - 1) / 2 {
+            bucket_len
+        } else {
+            calc_cap(bucket_len + 1)
+        };
+        if self.incremental_resize {
+            let bucket = Bucket::bucket_with_capacity(next_bucket_len);
+            let old = core::mem::replace(&mut self.bucket, bucket);
+            self.old = Some(Migration {
+                bucket: old,
+                next: 0,
+            });
+            self.growth_remaining = calc_bucket_len(next_bucket_len - 1) - self.current_size;
+        } else {
+            self.resize_to(next_bucket_len);
+        }
+    }
+
+    fn resize_to(&mut self, next_bucket_len: usize) {
+        self.migrate(usize::MAX);
         let mut new_bucket = Bucket::bucket_with_capacity(next_bucket_len);
-        for slot in self.bucket.0.drain(..) {
-            match slot {
-                Some((k, v)) => {
-                    let index = new_bucket.probe(&k);
-                    new_bucket.bucket_put(k, v, index);
-                }
-                None => continue,
-            };
+        for (k, v) in self.bucket.slots.drain(..).flatten() {
+            let hash = make_hash(&self.hash_builder, &k);
+            let index = new_bucket.find_insert_slot(hash);
+            new_bucket.bucket_put(k, v, hash, index);
         }
         self.bucket = new_bucket;
         self.growth_remaining = calc_bucket_len(next_bucket_len - 1) - self.current_size;
     }
 
-    pub fn with_capacity(capacity: usize) -> Self {
+    pub fn with_capacity_and_hasher(capacity: usize, hash_builder: S) -> Self {
         let bucket_len = calc_cap(capacity);
         let bucket = Bucket::bucket_with_capacity(bucket_len);
         Self {
             bucket,
+            old: None,
+            incremental_resize: false,
             growth_remaining: calc_bucket_len(bucket_len - 1),
             current_size: 0,
+            hash_builder,
         }
     }
-}
 
-fn calc_bucket_len(capacity: usize) -> usize {
-    // buckets smaller than 8 are not gonna be bothered with
-    if capacity < 8 {
-        capacity
-    } else {
-        (capacity + 1) / 8 * 7
+    pub fn with_hasher(hash_builder: S) -> Self {
+        Self::with_capacity_and_hasher(0, hash_builder)
+    }
+
+    pub fn hasher(&self) -> &S {
+        &self.hash_builder
     }
 }
 
-fn calc_cap(capacity: usize) -> usize {
-    // buckets smaller than 4 are unusable
-    if capacity < 8 {
-        if capacity < 4 { 4 } else { 8 }
-    } else {
-        let adjusted = capacity * 8 / 7;
-        adjusted.next_power_of_two()
+#[cfg(feature = "alloc")]
+impl<K, V, S> Default for HashMap<K, V, S>
+where
+    K: Hash + PartialEq,
+    S: BuildHasher + Default,
+{
+    fn default() -> Self {
+        Self::with_hasher(S::default())
     }
 }
 
-impl Hash for String {
-    fn hash(&self) -> usize {
-        self.chars()
-            .fold(0, |acc, byte| acc.rotate_left(1) ^ byte as usize)
+#[cfg(feature = "alloc")]
+impl<K, V, S> PartialEq for HashMap<K, V, S>
+where
+    K: Hash + PartialEq,
+    V: PartialEq,
+    S: BuildHasher,
+{
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len() && self.iter().all(|(k, v)| other.get(k) == Some(v))
     }
 }
 
-impl Hash for u16 {
-    fn hash(&self) -> usize {
-        *self as usize
+#[cfg(feature = "alloc")]
+impl<K, V, S> Eq for HashMap<K, V, S>
+where
+    K: Hash + Eq,
+    V: Eq,
+    S: BuildHasher,
+{
+}
+
+#[cfg(feature = "alloc")]
+impl<K, V, S> FromIterator<(K, V)> for HashMap<K, V, S>
+where
+    K: Hash + PartialEq,
+    S: BuildHasher + Default,
+{
+    fn from_iter<T: IntoIterator<Item = (K, V)>>(iter: T) -> Self {
+        let mut map = Self::default();
+        map.extend(iter);
+        map
     }
 }
 
-impl Hash for usize {
-    fn hash(&self) -> Self {
-        *self
+#[cfg(feature = "alloc")]
+impl<K, V, S> Extend<(K, V)> for HashMap<K, V, S>
+where
+    K: Hash + PartialEq,
+    S: BuildHasher,
+{
+    fn extend<T: IntoIterator<Item = (K, V)>>(&mut self, iter: T) {
+        let iter = iter.into_iter();
+        // duplicate keys may make this an overestimate, so only trust half of
+        // it once the map already has entries that could be overwritten
+        let reserve = if self.is_empty() {
+            iter.size_hint().0
+        } else {
+            iter.size_hint().0.div_ceil(2)
+        };
+        self.reserve(reserve);
+        for (k, v) in iter {
+            self.put(k, v);
+        }
     }
 }
 
-impl<K: Hash + PartialEq, V> IntoIterator for &mut HashMap<K, V> {
-    type Item = (K, V);
+#[cfg(feature = "alloc")]
+impl<K, Q, V, S> Index<&Q> for HashMap<K, V, S>
+where
+    K: Hash + PartialEq + Borrow<Q>,
+    Q: Hash + PartialEq + ?Sized,
+    S: BuildHasher,
+{
+    type Output = V;
+
+    /// Panics if `k` isn't in the map
+    fn index(&self, k: &Q) -> &Self::Output {
+        self.get(k).expect("key not found in HashMap")
+    }
+}
 
-    type IntoIter = std::vec::IntoIter<Self::Item>;
+fn calc_bucket_len(capacity: usize) -> usize {
+    // buckets smaller than 8 are not gonna be bothered with
+    if capacity < 8 {
+        capacity
+    } else {
+        (capacity + 1) / 8 * 7
+    }
+}
 
-    fn into_iter(self) -> Self::IntoIter {
-        self.bucket
-            .0
-            .iter_mut()
-            .filter_map(|slot| slot.take())
-            .collect::<Vec<_>>()
-            .into_iter()
+#[cfg(feature = "alloc")]
+fn calc_cap(capacity: usize) -> usize {
+    // buckets smaller than 4 are unusable
+    if capacity < 8 {
+        if capacity < 4 { 4 } else { 8 }
+    } else {
+        let adjusted = capacity * 8 / 7;
+        adjusted.next_power_of_two()
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "alloc"))]
 mod tests {
+    use alloc::{
+        string::{String, ToString},
+        vec::Vec,
+    };
+
     use super::*;
     #[test]
     fn set_get() {
@@ -232,4 +540,329 @@ mod tests {
         assert_eq!(map.current_size, arr.len().pow(2));
         assert_eq!(map.get(&String::from("Fositnsio")), None);
     }
+
+    #[test]
+    fn test_iterators() {
+        let mut map = HashMap::with_capacity(0);
+        for i in 0..100usize {
+            map.put(i, i * 2);
+        }
+        assert_eq!(map.iter().len(), 100);
+        let mut keys: Vec<_> = map.keys().copied().collect();
+        keys.sort();
+        assert_eq!(keys, (0..100).collect::<Vec<_>>());
+        for v in map.values_mut() {
+            *v += 1;
+        }
+        for (k, v) in &mut map {
+            *v += k;
+        }
+        for (k, v) in &map {
+            assert_eq!(*v, k * 3 + 1);
+        }
+        assert_eq!(
+            map.values().sum::<usize>(),
+            (0..100).map(|k| k * 3 + 1).sum()
+        );
+        let mut owned: Vec<_> = map.into_iter().collect();
+        owned.sort();
+        assert_eq!(owned.len(), 100);
+        assert_eq!(owned[99], (99, 298));
+    }
+
+    #[test]
+    fn test_drain() {
+        let mut map = HashMap::with_capacity(0);
+        for i in 0..50u16 {
+            map.put(i, i);
+        }
+        let mut drained: Vec<_> = map.drain().collect();
+        drained.sort();
+        assert_eq!(drained, (0..50).map(|i| (i, i)).collect::<Vec<_>>());
+        assert_eq!(map.current_size, 0);
+        assert_eq!(map.iter().next(), None);
+        assert_eq!(map.get(&7), None);
+
+        for i in 0..50u16 {
+            map.put(i, i + 1);
+        }
+        // a partially consumed drain still empties the map
+        assert_eq!(map.drain().take(3).count(), 3);
+        assert_eq!(map.iter().count(), 0);
+        map.put(3, 4);
+        assert_eq!(map.get(&3), Some(&4));
+        assert_eq!(map.iter().len(), 1);
+    }
+
+    #[test]
+    fn test_hashers() {
+        let mut map = HashMap::with_capacity_and_hasher(0, FxBuildHasher::default());
+        for i in 0..1000u32 {
+            map.put((i, i.to_string()), i);
+        }
+        for i in 0..1000u32 {
+            assert_eq!(map.get(&(i, i.to_string())), Some(&i));
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_random_state() {
+        let mut map = HashMap::with_hasher(RandomState::new());
+        map.put(['a', 'b'], true);
+        assert_eq!(map.get(&['a', 'b']), Some(&true));
+        assert_eq!(map.get(&['b', 'a']), None);
+    }
+
+    #[test]
+    fn test_borrowed_lookup() {
+        let mut map = HashMap::with_capacity(0);
+        map.put(String::from("Foo"), 1);
+        map.put(String::from("Bar"), 2);
+        assert_eq!(map.get("Foo"), Some(&1));
+        assert!(map.contains_key("Bar"));
+        assert!(!map.contains_key("Baz"));
+        *map.get_mut("Bar").unwrap() += 1;
+        assert_eq!(map.remove("Bar"), Some(3));
+        assert_eq!(map.remove("Bar"), None);
+        assert_eq!(map.get("Bar"), None);
+        assert_eq!(map.get("Foo"), Some(&1));
+    }
+
+    #[test]
+    fn test_remove() {
+        let mut map = HashMap::with_capacity(0);
+        for i in 0..1000usize {
+            map.put(i, i);
+        }
+        for i in (0..1000).step_by(2) {
+            assert_eq!(map.remove(&i), Some(i));
+        }
+        assert_eq!(map.current_size, 500);
+        for i in 0..1000 {
+            assert_eq!(map.get(&i), (i % 2 == 1).then_some(&i));
+        }
+        assert_eq!(map.iter().count(), 500);
+
+        // churning through the same few keys has to reuse tombstones
+        // rather than growing the bucket forever
+        let mut map = HashMap::with_capacity(0);
+        for i in 0..10_000usize {
+            map.put(i, i);
+            assert_eq!(map.remove(&i), Some(i));
+        }
+        assert!(map.bucket.len() <= 8);
+        assert_eq!(map.iter().next(), None);
+    }
+
+    #[test]
+    fn test_colliding_hasher() {
+        // every key lands on the same probe path with the same control byte
+        #[derive(Default)]
+        struct Collide;
+        impl Hasher for Collide {
+            fn write(&mut self, _: &[u8]) {}
+            fn finish(&self) -> u64 {
+                42
+            }
+        }
+        let mut map = HashMap::with_capacity_and_hasher(
+            0,
+            core::hash::BuildHasherDefault::<Collide>::default(),
+        );
+        for i in 0..100u16 {
+            map.put(i, i);
+        }
+        for i in (0..100).step_by(3) {
+            assert_eq!(map.remove(&i), Some(i));
+        }
+        for i in 0..100 {
+            assert_eq!(map.get(&i), (i % 3 != 0).then_some(&i));
+        }
+        for i in (0..100).step_by(3) {
+            assert_eq!(map.put(i, i + 1), None);
+        }
+        assert_eq!(map.iter().len(), 100);
+        assert_eq!(map.get(&99), Some(&100));
+    }
+
+    #[test]
+    fn test_incremental_resize() {
+        let mut map = HashMap::with_capacity(0);
+        map.set_incremental_resize(true);
+        let mut saw_resize = false;
+        for i in 0..u16::MAX {
+            map.put(i, i);
+            if map.is_resizing() {
+                saw_resize = true;
+                // entries on both sides of the migration are reachable
+                assert_eq!(map.get(&0), Some(&0));
+                assert_eq!(map.get(&(i / 2)), Some(&(i / 2)));
+                assert_eq!(map.iter().len(), i as usize + 1);
+            }
+            if i % 3 == 0 {
+                assert_eq!(map.remove(&(i / 3)), Some(i / 3));
+                assert_eq!(map.put(i / 3, i / 3), None);
+            }
+        }
+        assert!(saw_resize);
+        for i in 0..u16::MAX {
+            assert_eq!(map.get(&i), Some(&i));
+        }
+        assert_eq!(map.len(), u16::MAX as usize);
+        assert_eq!(map.iter().count(), u16::MAX as usize);
+
+        let expected: HashMap<_, _> = (0..u16::MAX).map(|i| (i, i)).collect();
+        assert_eq!(map, expected);
+
+        map.set_incremental_resize(false);
+        assert!(!map.is_resizing());
+    }
+
+    #[test]
+    fn test_incremental_resize_iterators() {
+        let mut map = HashMap::with_capacity(0);
+        map.set_incremental_resize(true);
+        let mut i = 0;
+        while !map.is_resizing() || i < 20 {
+            map.put(i, i);
+            i += 1;
+        }
+        assert!(map.is_resizing());
+        assert_eq!(map.clone().into_iter().count(), i);
+        for v in map.values_mut() {
+            *v += 1;
+        }
+        assert!(map.iter().all(|(k, v)| *v == k + 1));
+        let mut drained: Vec<_> = map.drain().map(|(k, _)| k).collect();
+        drained.sort();
+        assert_eq!(drained, (0..i).collect::<Vec<_>>());
+        assert!(map.is_empty() && !map.is_resizing());
+        map.put(1, 1);
+        assert_eq!(map.get(&1), Some(&1));
+    }
+
+    #[test]
+    fn test_capacity() {
+        // under 8 slots, all but one of them can be used
+        let mut map = HashMap::new();
+        assert_eq!(map.capacity(), 3);
+        assert_eq!(map.bucket.len(), 4);
+        for cap in 4..=7 {
+            assert_eq!(HashMap::<u8, u8>::with_capacity(cap).capacity(), 7);
+        }
+        for i in 0..3u8 {
+            map.put(i, i);
+        }
+        assert_eq!((map.len(), map.capacity(), map.bucket.len()), (3, 3, 4));
+        map.reserve(0);
+        assert_eq!(map.bucket.len(), 4);
+        map.reserve(1);
+        assert_eq!((map.capacity(), map.bucket.len()), (7, 8));
+        map.reserve(4);
+        assert_eq!(map.bucket.len(), 8);
+        map.reserve(5);
+        assert_eq!((map.capacity(), map.bucket.len()), (14, 16));
+
+        map.shrink_to_fit();
+        assert_eq!((map.capacity(), map.bucket.len()), (3, 4));
+        map.remove(&0);
+        map.remove(&1);
+        map.shrink_to_fit();
+        assert_eq!(map.bucket.len(), 4);
+        assert_eq!((map.len(), map.capacity()), (1, 3));
+        map.remove(&2);
+        assert!(map.is_empty());
+        map.shrink_to_fit();
+        assert_eq!(map.capacity(), 3);
+
+        let mut map = HashMap::with_capacity(1000);
+        for i in 0..10u16 {
+            map.put(i, i);
+        }
+        map.shrink_to_fit();
+        assert_eq!(map.capacity(), 14);
+        for i in 0..10 {
+            assert_eq!(map[&i], i);
+        }
+    }
+
+    #[test]
+    fn test_traits() {
+        let map: HashMap<String, usize> = ["Foo", "Bar", "Baz"]
+            .into_iter()
+            .map(|k| (String::from(k), k.len()))
+            .collect();
+        assert_eq!(map.len(), 3);
+        assert_eq!(map["Bar"], 3);
+        let mut other = map.clone();
+        assert_eq!(map, other);
+        other.extend([(String::from("Bar"), 4)]);
+        assert_ne!(map, other);
+        other.extend([(String::from("Bar"), 3)]);
+        assert_eq!(map, other);
+        other.extend([(String::from("Qoux"), 4)]);
+        assert_ne!(map, other);
+        let empty: HashMap<u8, u8, FxBuildHasher> = Default::default();
+        assert!(empty.is_empty());
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_index_missing() {
+        let map: HashMap<u8, u8> = HashMap::new();
+        let _ = map[&1];
+    }
+
+    #[test]
+    fn test_stats() {
+        let mut map = HashMap::with_capacity(0);
+        assert_eq!(map.stats().average_probe_length, 0.0);
+        assert!(map.stats().cluster_lengths.is_empty());
+        for i in 0..1000usize {
+            map.put(i, i);
+        }
+        for i in 0..100 {
+            map.remove(&i);
+        }
+        let stats = map.stats();
+        assert_eq!((stats.len, stats.bucket_len), (900, 2048));
+        assert_eq!(stats.tombstones, 100);
+        assert!((stats.load_factor - 900.0 / 2048.0).abs() < f64::EPSILON);
+        assert!(stats.average_probe_length >= 1.0 && stats.average_probe_length < 1.5);
+        assert_eq!(
+            stats
+                .cluster_lengths
+                .iter()
+                .map(|(l, n)| l * n)
+                .sum::<usize>(),
+            1000
+        );
+    }
+
+    #[test]
+    fn test_stats_bad_hasher() {
+        #[derive(Default)]
+        struct Collide;
+        impl Hasher for Collide {
+            fn write(&mut self, _: &[u8]) {}
+            fn finish(&self) -> u64 {
+                0
+            }
+        }
+        let mut map = HashMap::with_capacity_and_hasher(
+            0,
+            core::hash::BuildHasherDefault::<Collide>::default(),
+        );
+        for i in 0..100u16 {
+            map.put(i, i);
+        }
+        let stats = map.stats();
+        // every key walks the same probe path, so it gets longer with each
+        // of them and they pile up in a few long clusters
+        assert_eq!(stats.max_probe_length, 100usize.div_ceil(8));
+        assert!(stats.average_probe_length > 4.0);
+        assert!(stats.cluster_lengths.len() <= 3);
+        assert!(stats.cluster_lengths.keys().any(|length| *length > 50));
+    }
 }
@@ -0,0 +1,302 @@
+use alloc::boxed::Box;
+use core::{borrow::Borrow, hash::BuildHasher, iter::FusedIterator};
+
+use crate::{
+    DefaultHashBuilder, Hash, HashMap,
+    slab::{List, NIL, Slab},
+};
+
+/// Called with every entry a cache throws out to make room
+pub type EvictionCallback<K, V> = Box<dyn FnMut(K, V)>;
+
+/// Keeps at most `capacity` entries, throwing out the one that went unused
+/// the longest when a new one comes in.
+///
+/// Entries sit on a linked list ordered by last use, and the map points at
+/// their nodes. The map owns a copy of every key, hence the `Clone` bound.
+pub struct LruCache<K, V, S = DefaultHashBuilder>
+where
+    K: Hash + PartialEq,
+{
+    map: HashMap<K, usize, S>,
+    nodes: Slab<(K, V)>,
+    /// Most recently used at the head, least at the tail
+    order: List,
+    capacity: usize,
+    on_evict: Option<EvictionCallback<K, V>>,
+}
+
+impl<K, V> LruCache<K, V, DefaultHashBuilder>
+where
+    K: Hash + PartialEq + Clone,
+{
+    pub fn new(capacity: usize) -> Self {
+        Self::with_hasher(capacity, DefaultHashBuilder::default())
+    }
+}
+
+impl<K, V, S> LruCache<K, V, S>
+where
+    K: Hash + PartialEq + Clone,
+    S: BuildHasher,
+{
+    pub fn with_hasher(capacity: usize, hash_builder: S) -> Self {
+        Self {
+            map: HashMap::with_capacity_and_hasher(capacity, hash_builder),
+            nodes: Slab::with_capacity(capacity),
+            order: List::default(),
+            capacity,
+            on_evict: None,
+        }
+    }
+
+    pub fn set_eviction_callback(&mut self, on_evict: impl FnMut(K, V) + 'static) {
+        self.on_evict = Some(Box::new(on_evict));
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    fn touch(&mut self, index: usize) {
+        self.nodes.unlink(&mut self.order, index);
+        self.nodes.push_front(&mut self.order, index);
+    }
+
+    /// Marks `k` as the most recently used entry
+    pub fn get<Q>(&mut self, k: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + PartialEq + ?Sized,
+    {
+        let index = *self.map.get(k)?;
+        self.touch(index);
+        Some(&self.nodes.get(index).1)
+    }
+
+    /// Marks `k` as the most recently used entry
+    pub fn get_mut<Q>(&mut self, k: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + PartialEq + ?Sized,
+    {
+        let index = *self.map.get(k)?;
+        self.touch(index);
+        Some(&mut self.nodes.get_mut(index).1)
+    }
+
+    /// Looks at `k` without counting it as a use
+    pub fn peek<Q>(&self, k: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + PartialEq + ?Sized,
+    {
+        let index = *self.map.get(k)?;
+        Some(&self.nodes.get(index).1)
+    }
+
+    pub fn contains_key<Q>(&self, k: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + PartialEq + ?Sized,
+    {
+        self.map.contains_key(k)
+    }
+
+    /// The entry that would be evicted next
+    pub fn peek_lru(&self) -> Option<(&K, &V)> {
+        match self.order.tail {
+            NIL => None,
+            tail => {
+                let (k, v) = self.nodes.get(tail);
+                Some((k, v))
+            }
+        }
+    }
+
+    /// Returns the previous item if possible, otherwise None. Evicts the
+    /// least recently used entry if `k` is new and the cache is full.
+    pub fn put(&mut self, k: K, v: V) -> Option<V> {
+        if let Some(&index) = self.map.get(&k) {
+            self.touch(index);
+            return Some(core::mem::replace(&mut self.nodes.get_mut(index).1, v));
+        }
+        if self.capacity == 0 {
+            self.evicted(k, v);
+            return None;
+        }
+        if self.len() >= self.capacity {
+            self.evict();
+        }
+        let index = self.nodes.insert((k.clone(), v));
+        self.nodes.push_front(&mut self.order, index);
+        self.map.put(k, index);
+        None
+    }
+
+    pub fn remove<Q>(&mut self, k: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + PartialEq + ?Sized,
+    {
+        let index = *self.map.get(k)?;
+        Some(self.remove_index(index).1)
+    }
+
+    /// Removes the least recently used entry, without calling the eviction callback
+    pub fn pop_lru(&mut self) -> Option<(K, V)> {
+        match self.order.tail {
+            NIL => None,
+            tail => Some(self.remove_index(tail)),
+        }
+    }
+
+    fn remove_index(&mut self, index: usize) -> (K, V) {
+        self.nodes.unlink(&mut self.order, index);
+        let ((k, v), moved) = self.nodes.remove(index);
+        self.map.remove(&k);
+        if let Some(from) = moved {
+            self.order.relocate(from, index);
+            let moved_key = &self.nodes.get(index).0;
+            *self
+                .map
+                .get_mut(moved_key)
+                .expect("every node has a key in the map") = index;
+        }
+        (k, v)
+    }
+
+    fn evict(&mut self) {
+        if let Some((k, v)) = self.pop_lru() {
+            self.evicted(k, v);
+        }
+    }
+
+    fn evicted(&mut self, k: K, v: V) {
+        if let Some(on_evict) = &mut self.on_evict {
+            on_evict(k, v);
+        }
+    }
+
+    /// Evicts the least recently used entries until the cache fits in `capacity`
+    pub fn resize(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        while self.len() > capacity {
+            self.evict();
+        }
+    }
+
+    /// From most to least recently used
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter {
+            nodes: &self.nodes,
+            next: self.order.head,
+            remaining: self.len(),
+        }
+    }
+}
+
+pub struct Iter<'a, K, V> {
+    nodes: &'a Slab<(K, V)>,
+    next: usize,
+    remaining: usize,
+}
+
+impl<'a, K, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next == NIL {
+            return None;
+        }
+        let (k, v) = self.nodes.get(self.next);
+        self.next = self.nodes.next(self.next);
+        self.remaining -= 1;
+        Some((k, v))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<K, V> ExactSizeIterator for Iter<'_, K, V> {}
+impl<K, V> FusedIterator for Iter<'_, K, V> {}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{rc::Rc, vec::Vec};
+    use core::cell::RefCell;
+
+    use super::LruCache;
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let mut cache = LruCache::new(3);
+        cache.put("a", 1);
+        cache.put("b", 2);
+        cache.put("c", 3);
+        assert_eq!(cache.get("a"), Some(&1));
+        cache.put("d", 4);
+        assert!(!cache.contains_key("b"));
+        assert_eq!(
+            cache.iter().map(|(k, _)| *k).collect::<Vec<_>>(),
+            ["d", "a", "c"]
+        );
+        // peeking doesn't save "c"
+        assert_eq!(cache.peek("c"), Some(&3));
+        assert_eq!(cache.peek_lru(), Some((&"c", &3)));
+        cache.put("e", 5);
+        assert_eq!(cache.peek("c"), None);
+        assert_eq!(cache.put("a", 10), Some(1));
+        assert_eq!(cache.pop_lru(), Some(("d", 4)));
+        assert_eq!(cache.len(), 2);
+    }
+
+    #[test]
+    fn eviction_callback_and_resize() {
+        let evicted = Rc::new(RefCell::new(Vec::new()));
+        let mut cache = LruCache::new(4);
+        let sink = evicted.clone();
+        cache.set_eviction_callback(move |k, v| sink.borrow_mut().push((k, v)));
+        for i in 0..6 {
+            cache.put(i, i * 10);
+        }
+        assert_eq!(*evicted.borrow(), [(0, 0), (1, 10)]);
+        cache.resize(2);
+        assert_eq!(*evicted.borrow(), [(0, 0), (1, 10), (2, 20), (3, 30)]);
+        assert_eq!(cache.remove(&4), Some(40));
+        assert_eq!(evicted.borrow().len(), 4);
+        cache.resize(0);
+        cache.put(7, 70);
+        assert!(cache.is_empty());
+        assert_eq!(evicted.borrow().last(), Some(&(7, 70)));
+    }
+
+    #[test]
+    fn churn() {
+        let mut cache = LruCache::new(100);
+        for i in 0..10_000usize {
+            cache.put(i, i);
+            if i % 7 == 0 {
+                cache.remove(&(i - i % 50));
+            }
+            if i % 3 == 0 {
+                cache.get(&(i / 2));
+            }
+        }
+        assert_eq!(cache.len(), 100);
+        for (k, v) in cache.iter() {
+            assert_eq!(cache.peek(k), Some(v));
+        }
+        assert_eq!(cache.iter().len(), 100);
+    }
+}
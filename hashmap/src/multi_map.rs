@@ -0,0 +1,466 @@
+use alloc::{
+    collections::{VecDeque, vec_deque},
+    vec::Vec,
+};
+use core::{borrow::Borrow, hash::BuildHasher, iter::FusedIterator};
+
+use crate::{DefaultHashBuilder, Hash, HashMap};
+
+/// A map from each key to any number of values, kept in the order they
+/// were inserted. A key is only in the map while it has values.
+#[derive(Debug, Clone)]
+pub struct MultiMap<K, V, S = DefaultHashBuilder>
+where
+    K: Hash + PartialEq,
+{
+    map: HashMap<K, VecDeque<V>, S>,
+    /// Values over all keys
+    len: usize,
+}
+
+impl<K, V> MultiMap<K, V, DefaultHashBuilder>
+where
+    K: Hash + PartialEq,
+{
+    pub fn new() -> Self {
+        Self::with_hasher(DefaultHashBuilder::default())
+    }
+
+    /// Room for `capacity` distinct keys
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self::with_capacity_and_hasher(capacity, DefaultHashBuilder::default())
+    }
+}
+
+impl<K, V, S> MultiMap<K, V, S>
+where
+    K: Hash + PartialEq,
+{
+    /// Number of values, counting every value of every key
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Number of distinct keys
+    pub fn keys_len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Every key and value pair, the values of a key one after another
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter {
+            keys: self.map.iter(),
+            current: None,
+            remaining: self.len,
+        }
+    }
+
+    /// Every distinct key once
+    pub fn keys(&self) -> Keys<'_, K, V> {
+        Keys(self.map.keys())
+    }
+
+    pub fn values(&self) -> Values<'_, K, V> {
+        Values(self.iter())
+    }
+
+    pub fn clear(&mut self) {
+        self.map.drain();
+        self.len = 0;
+    }
+}
+
+impl<K, V, S> MultiMap<K, V, S>
+where
+    K: Hash + PartialEq,
+    S: BuildHasher,
+{
+    pub fn with_capacity_and_hasher(capacity: usize, hash_builder: S) -> Self {
+        Self {
+            map: HashMap::with_capacity_and_hasher(capacity, hash_builder),
+            len: 0,
+        }
+    }
+
+    pub fn with_hasher(hash_builder: S) -> Self {
+        Self::with_capacity_and_hasher(0, hash_builder)
+    }
+
+    /// Adds `v` after the values `k` already has
+    pub fn insert(&mut self, k: K, v: V) {
+        self.len += 1;
+        match self.map.get_mut(&k) {
+            Some(values) => values.push_back(v),
+            None => {
+                self.map.put(k, VecDeque::from([v]));
+            }
+        }
+    }
+
+    /// The first value of `k`
+    pub fn get<Q>(&self, k: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + PartialEq + ?Sized,
+    {
+        self.map.get(k)?.front()
+    }
+
+    /// Every value of `k` in insertion order, nothing if `k` isn't there
+    pub fn get_all<Q>(&self, k: &Q) -> GetAll<'_, V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + PartialEq + ?Sized,
+    {
+        GetAll(self.map.get(k).map(VecDeque::iter).unwrap_or_default())
+    }
+
+    /// Same as `get_all`, but the values can be modified in place
+    pub fn get_all_mut<Q>(&mut self, k: &Q) -> GetAllMut<'_, V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + PartialEq + ?Sized,
+    {
+        GetAllMut(
+            self.map
+                .get_mut(k)
+                .map(VecDeque::iter_mut)
+                .unwrap_or_default(),
+        )
+    }
+
+    /// How many values `k` has
+    pub fn count<Q>(&self, k: &Q) -> usize
+    where
+        K: Borrow<Q>,
+        Q: Hash + PartialEq + ?Sized,
+    {
+        self.map.get(k).map_or(0, VecDeque::len)
+    }
+
+    pub fn contains_key<Q>(&self, k: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + PartialEq + ?Sized,
+    {
+        self.map.contains_key(k)
+    }
+
+    /// Removes the first value of `k`, and `k` itself along with its last value
+    pub fn remove_one<Q>(&mut self, k: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + PartialEq + ?Sized,
+    {
+        let values = self.map.get_mut(k)?;
+        let v = values
+            .pop_front()
+            .expect("a key is only in the map while it has values");
+        if values.is_empty() {
+            self.map.remove(k);
+        }
+        self.len -= 1;
+        Some(v)
+    }
+
+    /// Removes `k` and hands back all of its values in insertion order
+    pub fn remove_all<Q>(&mut self, k: &Q) -> RemoveAll<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + PartialEq + ?Sized,
+    {
+        let values = self.map.remove(k).unwrap_or_default();
+        self.len -= values.len();
+        RemoveAll(values.into_iter())
+    }
+
+    /// Keeps only the values `f` returns true for, dropping keys left without any
+    pub fn retain(&mut self, mut f: impl FnMut(&K, &V) -> bool)
+    where
+        K: Clone,
+    {
+        let mut emptied = Vec::new();
+        for (k, values) in self.map.iter_mut() {
+            let before = values.len();
+            values.retain(|v| f(k, v));
+            self.len -= before - values.len();
+            if values.is_empty() {
+                emptied.push(k.clone());
+            }
+        }
+        for k in emptied {
+            self.map.remove(&k);
+        }
+    }
+}
+
+impl<K, V, S> Default for MultiMap<K, V, S>
+where
+    K: Hash + PartialEq,
+    S: BuildHasher + Default,
+{
+    fn default() -> Self {
+        Self::with_hasher(S::default())
+    }
+}
+
+impl<K, V, S> PartialEq for MultiMap<K, V, S>
+where
+    K: Hash + PartialEq,
+    V: PartialEq,
+    S: BuildHasher,
+{
+    /// Values have to be in the same order for each key
+    fn eq(&self, other: &Self) -> bool {
+        self.len == other.len && self.map == other.map
+    }
+}
+
+impl<K, V, S> FromIterator<(K, V)> for MultiMap<K, V, S>
+where
+    K: Hash + PartialEq,
+    S: BuildHasher + Default,
+{
+    fn from_iter<T: IntoIterator<Item = (K, V)>>(iter: T) -> Self {
+        let mut map = Self::default();
+        map.extend(iter);
+        map
+    }
+}
+
+impl<K, V, S> Extend<(K, V)> for MultiMap<K, V, S>
+where
+    K: Hash + PartialEq,
+    S: BuildHasher,
+{
+    fn extend<T: IntoIterator<Item = (K, V)>>(&mut self, iter: T) {
+        for (k, v) in iter {
+            self.insert(k, v);
+        }
+    }
+}
+
+impl<'a, K: Hash + PartialEq, V, S> IntoIterator for &'a MultiMap<K, V, S> {
+    type Item = (&'a K, &'a V);
+
+    type IntoIter = Iter<'a, K, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+pub struct Iter<'a, K, V> {
+    keys: crate::Iter<'a, K, VecDeque<V>>,
+    /// The key whose values are being walked through
+    current: Option<(&'a K, vec_deque::Iter<'a, V>)>,
+    remaining: usize,
+}
+
+impl<'a, K, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((k, values)) = &mut self.current
+                && let Some(v) = values.next()
+            {
+                self.remaining -= 1;
+                return Some((*k, v));
+            }
+            let (k, values) = self.keys.next()?;
+            self.current = Some((k, values.iter()));
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<K, V> Clone for Iter<'_, K, V> {
+    fn clone(&self) -> Self {
+        Self {
+            keys: self.keys.clone(),
+            current: self.current.clone(),
+            remaining: self.remaining,
+        }
+    }
+}
+
+pub struct Keys<'a, K, V>(crate::Keys<'a, K, VecDeque<V>>);
+
+impl<'a, K, V> Iterator for Keys<'a, K, V> {
+    type Item = &'a K;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+impl<K, V> Clone for Keys<'_, K, V> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+pub struct Values<'a, K, V>(Iter<'a, K, V>);
+
+impl<'a, K, V> Iterator for Values<'a, K, V> {
+    type Item = &'a V;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(|(_, v)| v)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+pub struct GetAll<'a, V>(vec_deque::Iter<'a, V>);
+
+impl<'a, V> Iterator for GetAll<'a, V> {
+    type Item = &'a V;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+impl<V> DoubleEndedIterator for GetAll<'_, V> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.0.next_back()
+    }
+}
+
+pub struct GetAllMut<'a, V>(vec_deque::IterMut<'a, V>);
+
+impl<'a, V> Iterator for GetAllMut<'a, V> {
+    type Item = &'a mut V;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+impl<V> DoubleEndedIterator for GetAllMut<'_, V> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.0.next_back()
+    }
+}
+
+pub struct RemoveAll<V>(vec_deque::IntoIter<V>);
+
+impl<V> Iterator for RemoveAll<V> {
+    type Item = V;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+impl<V> DoubleEndedIterator for RemoveAll<V> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.0.next_back()
+    }
+}
+
+impl<K, V> ExactSizeIterator for Iter<'_, K, V> {}
+impl<K, V> ExactSizeIterator for Keys<'_, K, V> {}
+impl<K, V> ExactSizeIterator for Values<'_, K, V> {}
+impl<V> ExactSizeIterator for GetAll<'_, V> {}
+impl<V> ExactSizeIterator for GetAllMut<'_, V> {}
+impl<V> ExactSizeIterator for RemoveAll<V> {}
+
+impl<K, V> FusedIterator for Iter<'_, K, V> {}
+impl<K, V> FusedIterator for Keys<'_, K, V> {}
+impl<K, V> FusedIterator for Values<'_, K, V> {}
+impl<V> FusedIterator for GetAll<'_, V> {}
+impl<V> FusedIterator for GetAllMut<'_, V> {}
+impl<V> FusedIterator for RemoveAll<V> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn insert_and_get() {
+        let mut map = MultiMap::new();
+        map.insert("fruit", "apple");
+        map.insert("veg", "leek");
+        map.insert("fruit", "pear");
+        map.insert("fruit", "plum");
+        assert_eq!(map.len(), 4);
+        assert_eq!(map.keys_len(), 2);
+        assert_eq!(map.get("fruit"), Some(&"apple"));
+        assert_eq!(
+            map.get_all("fruit").collect::<Vec<_>>(),
+            [&"apple", &"pear", &"plum"]
+        );
+        assert_eq!(map.get_all("meat").len(), 0);
+        assert_eq!(map.count("fruit"), 3);
+        assert_eq!(map.count("meat"), 0);
+        for v in map.get_all_mut("veg") {
+            *v = "onion";
+        }
+        assert_eq!(map.get("veg"), Some(&"onion"));
+    }
+
+    #[test]
+    fn removing() {
+        let mut map: MultiMap<u8, u8> = [(1, 10), (2, 20), (1, 11), (1, 12)].into_iter().collect();
+        assert_eq!(map.remove_one(&1), Some(10));
+        assert_eq!(map.remove_one(&2), Some(20));
+        assert!(!map.contains_key(&2));
+        assert_eq!(map.remove_one(&2), None);
+        assert_eq!(map.len(), 2);
+        assert_eq!(map.remove_all(&1).collect::<Vec<_>>(), [11, 12]);
+        assert_eq!(map.remove_all(&1).len(), 0);
+        assert!(map.is_empty() && map.keys_len() == 0);
+    }
+
+    #[test]
+    fn flattening_iterators() {
+        let mut map = MultiMap::new();
+        for i in 0..100u32 {
+            map.insert(i % 7, i);
+        }
+        assert_eq!(map.iter().len(), 100);
+        let mut pairs: Vec<_> = map.iter().map(|(k, v)| (*k, *v)).collect();
+        pairs.sort();
+        let mut expected: Vec<_> = (0..100).map(|i| (i % 7, i)).collect();
+        expected.sort();
+        assert_eq!(pairs, expected);
+        assert_eq!(map.values().sum::<u32>(), (0..100).sum());
+        assert_eq!(map.keys().count(), 7);
+
+        map.retain(|k, v| *k != 3 && v % 2 == 0);
+        assert_eq!(map.len(), map.iter().count());
+        assert!(!map.contains_key(&3));
+        assert!(map.values().all(|v| v % 2 == 0));
+        map.clear();
+        assert!(map.is_empty());
+        assert_eq!(map.iter().next(), None);
+    }
+}
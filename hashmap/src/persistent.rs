@@ -0,0 +1,659 @@
+use alloc::{sync::Arc, vec, vec::Vec};
+use core::{borrow::Borrow, fmt, hash::BuildHasher, iter::FusedIterator, mem, slice};
+
+use crate::{DefaultHashBuilder, Hash, make_hash};
+
+/// Bits of the hash used to pick a child at each level
+const BITS: u32 = 5;
+const LEVEL_MASK: usize = (1 << BITS) - 1;
+
+/// Which of the 32 children of a branch at depth `shift` the hash belongs to,
+/// as a single bit of the branch's bitmap
+fn bit(hash: usize, shift: u32) -> u32 {
+    1 << ((hash >> shift) & LEVEL_MASK)
+}
+
+/// Where the child for `bit` sits in a branch's dense list of children
+fn position(bitmap: u32, bit: u32) -> usize {
+    (bitmap & (bit - 1)).count_ones() as usize
+}
+
+#[derive(Clone)]
+enum Node<K, V> {
+    /// Only children that exist are stored, `bitmap` says which ones they are
+    Branch {
+        bitmap: u32,
+        children: Vec<Child<K, V>>,
+    },
+    /// Keys whose whole hash is the same, which no amount of branching can separate
+    Collision { hash: usize, entries: Vec<(K, V)> },
+}
+
+#[derive(Clone)]
+enum Child<K, V> {
+    Leaf(usize, K, V),
+    Node(Arc<Node<K, V>>),
+}
+
+impl<K, V> Node<K, V> {
+    fn empty() -> Self {
+        Node::Branch {
+            bitmap: 0,
+            children: Vec::new(),
+        }
+    }
+
+    /// A node at depth `shift` holding two entries with different keys
+    fn pair(shift: u32, first: (usize, K, V), second: (usize, K, V)) -> Self {
+        if first.0 == second.0 {
+            return Node::Collision {
+                hash: first.0,
+                entries: vec![(first.1, first.2), (second.1, second.2)],
+            };
+        }
+        let (first_bit, second_bit) = (bit(first.0, shift), bit(second.0, shift));
+        let children = if first_bit == second_bit {
+            vec![Child::Node(Arc::new(Node::pair(
+                shift + BITS,
+                first,
+                second,
+            )))]
+        } else {
+            let first = Child::Leaf(first.0, first.1, first.2);
+            let second = Child::Leaf(second.0, second.1, second.2);
+            if first_bit < second_bit {
+                vec![first, second]
+            } else {
+                vec![second, first]
+            }
+        };
+        Node::Branch {
+            bitmap: first_bit | second_bit,
+            children,
+        }
+    }
+}
+
+impl<K, V> Node<K, V>
+where
+    K: PartialEq + Clone,
+    V: Clone,
+{
+    /// Copies the node first if another version still shares it
+    fn put(node: &mut Arc<Self>, hash: usize, shift: u32, k: K, v: V) -> Option<V> {
+        match Arc::make_mut(node) {
+            Node::Branch { bitmap, children } => {
+                let bit = bit(hash, shift);
+                let position = position(*bitmap, bit);
+                if *bitmap & bit == 0 {
+                    *bitmap |= bit;
+                    children.insert(position, Child::Leaf(hash, k, v));
+                    return None;
+                }
+                match &mut children[position] {
+                    Child::Node(child) => Node::put(child, hash, shift + BITS, k, v),
+                    Child::Leaf(leaf_hash, leaf_k, leaf_v)
+                        if *leaf_hash == hash && *leaf_k == k =>
+                    {
+                        *leaf_k = k;
+                        Some(mem::replace(leaf_v, v))
+                    }
+                    child => {
+                        // two keys share the bits so far, push both a level down
+                        let Child::Leaf(leaf_hash, leaf_k, leaf_v) =
+                            mem::replace(child, Child::Node(Arc::new(Node::empty())))
+                        else {
+                            unreachable!("nodes were matched above")
+                        };
+                        let pair =
+                            Node::pair(shift + BITS, (leaf_hash, leaf_k, leaf_v), (hash, k, v));
+                        *child = Child::Node(Arc::new(pair));
+                        None
+                    }
+                }
+            }
+            Node::Collision {
+                hash: collision_hash,
+                entries,
+            } if *collision_hash == hash => {
+                match entries.iter_mut().find(|(entry_k, _)| *entry_k == k) {
+                    Some((entry_k, entry_v)) => {
+                        *entry_k = k;
+                        Some(mem::replace(entry_v, v))
+                    }
+                    None => {
+                        entries.push((k, v));
+                        None
+                    }
+                }
+            }
+            Node::Collision {
+                hash: collision_hash,
+                ..
+            } => {
+                // a different hash made it down here, branch out above the collision
+                let bitmap = bit(*collision_hash, shift);
+                let collision = mem::replace(Arc::make_mut(node), Node::empty());
+                *Arc::make_mut(node) = Node::Branch {
+                    bitmap,
+                    children: vec![Child::Node(Arc::new(collision))],
+                };
+                Node::put(node, hash, shift, k, v)
+            }
+        }
+    }
+
+    /// Callers check the key is there first, so nothing is copied for nothing
+    fn remove<Q>(node: &mut Arc<Self>, hash: usize, shift: u32, k: &Q) -> Option<(K, V)>
+    where
+        K: Borrow<Q>,
+        Q: PartialEq + ?Sized,
+    {
+        match Arc::make_mut(node) {
+            Node::Branch { bitmap, children } => {
+                let bit = bit(hash, shift);
+                if *bitmap & bit == 0 {
+                    return None;
+                }
+                let position = position(*bitmap, bit);
+                match &mut children[position] {
+                    Child::Leaf(_, leaf_k, _) if k.eq((*leaf_k).borrow()) => {
+                        *bitmap &= !bit;
+                        let Child::Leaf(_, k, v) = children.remove(position) else {
+                            unreachable!("a leaf was matched above")
+                        };
+                        Some((k, v))
+                    }
+                    Child::Leaf(..) => None,
+                    Child::Node(child) => {
+                        let removed = Node::remove(child, hash, shift + BITS, k)?;
+                        // a node down to its last entry is just a leaf with extra steps
+                        if let Some(leaf) = Node::take_single_leaf(child) {
+                            children[position] = leaf;
+                        }
+                        Some(removed)
+                    }
+                }
+            }
+            Node::Collision { entries, .. } => {
+                let index = entries
+                    .iter()
+                    .position(|(entry_k, _)| k.eq(entry_k.borrow()))?;
+                Some(entries.swap_remove(index))
+            }
+        }
+    }
+
+    fn take_single_leaf(node: &mut Arc<Self>) -> Option<Child<K, V>> {
+        match Arc::make_mut(node) {
+            Node::Branch { children, .. }
+                if children.len() == 1 && matches!(children[0], Child::Leaf(..)) =>
+            {
+                children.pop()
+            }
+            Node::Collision { hash, entries } if entries.len() == 1 => {
+                let (k, v) = entries.pop()?;
+                Some(Child::Leaf(*hash, k, v))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// An immutable map, where `insert` and `remove` return a new version and
+/// leave the old one as it was.
+///
+/// It's a hash array mapped trie: every level picks one of 32 children with
+/// 5 bits of the hash, so nothing is ever more than `usize::BITS / 5 + 1`
+/// levels deep. Versions share every node an update didn't touch, making
+/// updates O(log32 n) copies and `clone` a reference count bump.
+pub struct PersistentHashMap<K, V, S = DefaultHashBuilder>
+where
+    K: Hash + PartialEq,
+{
+    root: Arc<Node<K, V>>,
+    len: usize,
+    hash_builder: S,
+}
+
+impl<K, V> PersistentHashMap<K, V, DefaultHashBuilder>
+where
+    K: Hash + PartialEq,
+{
+    pub fn new() -> Self {
+        Self::with_hasher(DefaultHashBuilder::default())
+    }
+}
+
+impl<K, V, S> PersistentHashMap<K, V, S>
+where
+    K: Hash + PartialEq,
+{
+    pub fn with_hasher(hash_builder: S) -> Self {
+        Self {
+            root: Arc::new(Node::empty()),
+            len: 0,
+            hash_builder,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        let Node::Branch { children, .. } = &*self.root else {
+            unreachable!("the root is always a branch")
+        };
+        Iter {
+            stack: vec![children.iter()],
+            collision: [].iter(),
+            remaining: self.len,
+        }
+    }
+
+    pub fn hasher(&self) -> &S {
+        &self.hash_builder
+    }
+}
+
+impl<K, V, S> PersistentHashMap<K, V, S>
+where
+    K: Hash + PartialEq,
+    S: BuildHasher,
+{
+    pub fn get<Q>(&self, k: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + PartialEq + ?Sized,
+    {
+        let hash = make_hash(&self.hash_builder, k);
+        let mut node = &*self.root;
+        let mut shift = 0;
+        loop {
+            match node {
+                Node::Branch { bitmap, children } => {
+                    let bit = bit(hash, shift);
+                    if bitmap & bit == 0 {
+                        return None;
+                    }
+                    match &children[position(*bitmap, bit)] {
+                        Child::Leaf(leaf_hash, leaf_k, leaf_v) => {
+                            return (*leaf_hash == hash && k.eq(leaf_k.borrow())).then_some(leaf_v);
+                        }
+                        Child::Node(child) => {
+                            node = child;
+                            shift += BITS;
+                        }
+                    }
+                }
+                Node::Collision { entries, .. } => {
+                    return entries
+                        .iter()
+                        .find(|(entry_k, _)| k.eq(entry_k.borrow()))
+                        .map(|(_, v)| v);
+                }
+            }
+        }
+    }
+
+    pub fn contains_key<Q>(&self, k: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + PartialEq + ?Sized,
+    {
+        self.get(k).is_some()
+    }
+}
+
+impl<K, V, S> PersistentHashMap<K, V, S>
+where
+    K: Hash + PartialEq + Clone,
+    V: Clone,
+    S: BuildHasher + Clone,
+{
+    /// A new version with `k` set to `v`
+    pub fn insert(&self, k: K, v: V) -> Self {
+        let mut map = self.clone();
+        map.put_in_place(k, v);
+        map
+    }
+
+    /// A new version without `k`
+    pub fn remove<Q>(&self, k: &Q) -> Self
+    where
+        K: Borrow<Q>,
+        Q: Hash + PartialEq + ?Sized,
+    {
+        let mut map = self.clone();
+        map.remove_in_place(k);
+        map
+    }
+
+    /// A mutable copy for making many changes at once. Only the first change
+    /// to each node copies it, after that the transient owns it outright.
+    pub fn transient(&self) -> TransientHashMap<K, V, S> {
+        TransientHashMap { map: self.clone() }
+    }
+
+    fn put_in_place(&mut self, k: K, v: V) -> Option<V> {
+        let hash = make_hash(&self.hash_builder, &k);
+        let previous = Node::put(&mut self.root, hash, 0, k, v);
+        if previous.is_none() {
+            self.len += 1;
+        }
+        previous
+    }
+
+    fn remove_in_place<Q>(&mut self, k: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + PartialEq + ?Sized,
+    {
+        if !self.contains_key(k) {
+            return None;
+        }
+        let hash = make_hash(&self.hash_builder, k);
+        let (_, v) = Node::remove(&mut self.root, hash, 0, k)?;
+        self.len -= 1;
+        Some(v)
+    }
+}
+
+impl<K, V, S> Clone for PersistentHashMap<K, V, S>
+where
+    K: Hash + PartialEq,
+    S: Clone,
+{
+    fn clone(&self) -> Self {
+        Self {
+            root: Arc::clone(&self.root),
+            len: self.len,
+            hash_builder: self.hash_builder.clone(),
+        }
+    }
+}
+
+impl<K, V, S> Default for PersistentHashMap<K, V, S>
+where
+    K: Hash + PartialEq,
+    S: Default,
+{
+    fn default() -> Self {
+        Self::with_hasher(S::default())
+    }
+}
+
+impl<K, V, S> fmt::Debug for PersistentHashMap<K, V, S>
+where
+    K: Hash + PartialEq + fmt::Debug,
+    V: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl<K, V, S> FromIterator<(K, V)> for PersistentHashMap<K, V, S>
+where
+    K: Hash + PartialEq + Clone,
+    V: Clone,
+    S: BuildHasher + Clone + Default,
+{
+    fn from_iter<T: IntoIterator<Item = (K, V)>>(iter: T) -> Self {
+        let mut transient = Self::default().transient();
+        transient.extend(iter);
+        transient.persistent()
+    }
+}
+
+impl<'a, K: Hash + PartialEq, V, S> IntoIterator for &'a PersistentHashMap<K, V, S> {
+    type Item = (&'a K, &'a V);
+
+    type IntoIter = Iter<'a, K, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// A [`PersistentHashMap`] that is changed in place, for bulk loads. Nodes
+/// it shares with the map it came from are copied the first time they're
+/// changed, the ones it already owns are changed directly.
+pub struct TransientHashMap<K, V, S = DefaultHashBuilder>
+where
+    K: Hash + PartialEq,
+{
+    map: PersistentHashMap<K, V, S>,
+}
+
+impl<K, V, S> TransientHashMap<K, V, S>
+where
+    K: Hash + PartialEq + Clone,
+    V: Clone,
+    S: BuildHasher + Clone,
+{
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    pub fn get<Q>(&self, k: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + PartialEq + ?Sized,
+    {
+        self.map.get(k)
+    }
+
+    pub fn contains_key<Q>(&self, k: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + PartialEq + ?Sized,
+    {
+        self.map.contains_key(k)
+    }
+
+    /// Returns the previous item if possible, otherwise None
+    pub fn put(&mut self, k: K, v: V) -> Option<V> {
+        self.map.put_in_place(k, v)
+    }
+
+    pub fn remove<Q>(&mut self, k: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + PartialEq + ?Sized,
+    {
+        self.map.remove_in_place(k)
+    }
+
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        self.map.iter()
+    }
+
+    /// Freezes the changes into a version that can be shared again
+    pub fn persistent(self) -> PersistentHashMap<K, V, S> {
+        self.map
+    }
+}
+
+impl<K, V, S> Extend<(K, V)> for TransientHashMap<K, V, S>
+where
+    K: Hash + PartialEq + Clone,
+    V: Clone,
+    S: BuildHasher + Clone,
+{
+    fn extend<T: IntoIterator<Item = (K, V)>>(&mut self, iter: T) {
+        for (k, v) in iter {
+            self.put(k, v);
+        }
+    }
+}
+
+/// Depth first over the trie, so in no particular order
+pub struct Iter<'a, K, V> {
+    /// Children of every branch on the way down that are still to be visited
+    stack: Vec<slice::Iter<'a, Child<K, V>>>,
+    collision: slice::Iter<'a, (K, V)>,
+    remaining: usize,
+}
+
+impl<'a, K, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((k, v)) = self.collision.next() {
+                self.remaining -= 1;
+                return Some((k, v));
+            }
+            match self.stack.last_mut()?.next() {
+                None => {
+                    self.stack.pop();
+                }
+                Some(Child::Leaf(_, k, v)) => {
+                    self.remaining -= 1;
+                    return Some((k, v));
+                }
+                Some(Child::Node(node)) => match &**node {
+                    Node::Branch { children, .. } => self.stack.push(children.iter()),
+                    Node::Collision { entries, .. } => self.collision = entries.iter(),
+                },
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<K, V> ExactSizeIterator for Iter<'_, K, V> {}
+impl<K, V> FusedIterator for Iter<'_, K, V> {}
+
+#[cfg(test)]
+mod tests {
+    use core::hash::{BuildHasherDefault, Hasher};
+
+    use super::*;
+    use crate::FxBuildHasher;
+
+    #[test]
+    fn versions_are_independent() {
+        let empty = PersistentHashMap::new();
+        let one = empty.insert("a", 1);
+        let two = one.insert("b", 2);
+        let changed = two.insert("a", 10);
+        let removed = changed.remove("b");
+        assert!(empty.is_empty());
+        assert_eq!(one.get("a"), Some(&1));
+        assert_eq!(one.get("b"), None);
+        assert_eq!((two.get("a"), two.get("b")), (Some(&1), Some(&2)));
+        assert_eq!((changed.get("a"), changed.len()), (Some(&10), 2));
+        assert_eq!((removed.get("b"), removed.len()), (None, 1));
+        assert_eq!(removed.remove("missing").len(), 1);
+    }
+
+    #[test]
+    fn many_versions() {
+        let mut versions = vec![PersistentHashMap::<u32, u32, FxBuildHasher>::default()];
+        for i in 0..2000 {
+            let last = versions.last().unwrap();
+            versions.push(if i % 5 == 4 {
+                last.remove(&(i - 2))
+            } else {
+                last.insert(i, i)
+            });
+        }
+        for (version, map) in versions.iter().enumerate() {
+            // every fifth step removes the key put in two steps before
+            let expected = (0..version as u32)
+                .filter(|i| i % 5 != 4 && (i % 5 != 2 || i + 2 >= version as u32));
+            let mut keys: Vec<_> = map.iter().map(|(k, _)| *k).collect();
+            keys.sort();
+            assert_eq!(keys, expected.collect::<Vec<_>>());
+            assert_eq!(map.iter().len(), map.len());
+        }
+    }
+
+    #[test]
+    fn transient_bulk_load() {
+        let base: PersistentHashMap<u32, u32> = (0..10_000).map(|i| (i, i)).collect();
+        let mut transient = base.transient();
+        for i in (0..10_000).step_by(2) {
+            assert_eq!(transient.remove(&i), Some(i));
+        }
+        for i in 10_000..12_000 {
+            assert_eq!(transient.put(i, i), None);
+        }
+        assert_eq!(transient.put(1, 100), Some(1));
+        let updated = transient.persistent();
+        assert_eq!(updated.len(), 7_000);
+        assert_eq!(updated.get(&1), Some(&100));
+        assert_eq!(updated.get(&2), None);
+        assert_eq!(updated.get(&11_999), Some(&11_999));
+        // the base never saw any of it
+        assert_eq!(base.len(), 10_000);
+        assert!((0..10_000).all(|i| base.get(&i) == Some(&i)));
+    }
+
+    #[test]
+    fn colliding_hashes() {
+        #[derive(Default)]
+        struct Collide;
+        impl Hasher for Collide {
+            fn write(&mut self, _: &[u8]) {}
+            fn finish(&self) -> u64 {
+                0b10110
+            }
+        }
+        let base = PersistentHashMap::with_hasher(BuildHasherDefault::<Collide>::default());
+        let mut map = base.clone();
+        for i in 0..50u8 {
+            map = map.insert(i, i);
+        }
+        assert_eq!(map.len(), 50);
+        assert!((0..50).all(|i| map.get(&i) == Some(&i)));
+        for i in 0..49 {
+            map = map.remove(&i);
+        }
+        assert_eq!(map.iter().collect::<Vec<_>>(), [(&49, &49)]);
+        assert!(base.is_empty());
+    }
+
+    #[test]
+    fn shared_prefixes() {
+        // only the top bits differ, so every pair has to go many levels down
+        #[derive(Default)]
+        struct TopBits(u64);
+        impl Hasher for TopBits {
+            fn write(&mut self, bytes: &[u8]) {
+                for byte in bytes {
+                    self.0 = self.0 << 8 | *byte as u64;
+                }
+            }
+            fn finish(&self) -> u64 {
+                self.0 << 58
+            }
+        }
+        let mut map = PersistentHashMap::with_hasher(BuildHasherDefault::<TopBits>::default());
+        // 64 and up share their hash with a smaller key, those end up colliding
+        for i in 0..100u8 {
+            map = map.insert(i, i);
+        }
+        assert!((0..100).all(|i| map.get(&i) == Some(&i)));
+        for i in (0..100).step_by(2) {
+            map = map.remove(&i);
+        }
+        assert_eq!(map.len(), 50);
+        assert!((0..100).all(|i| map.get(&i) == (i % 2 == 1).then_some(&i)));
+        assert_eq!(map.iter().count(), 50);
+    }
+}
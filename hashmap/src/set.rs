@@ -0,0 +1,420 @@
+use core::{borrow::Borrow, hash::BuildHasher, iter::FusedIterator};
+
+use crate::{DefaultHashBuilder, Hash, HashMap};
+
+/// A [`HashMap`] with nothing but keys
+#[derive(Debug)]
+pub struct HashSet<K, S = DefaultHashBuilder>
+where
+    K: Hash + PartialEq,
+{
+    map: HashMap<K, (), S>,
+}
+
+impl<K> HashSet<K, DefaultHashBuilder>
+where
+    K: Hash + PartialEq,
+{
+    pub fn new() -> Self {
+        Self::with_hasher(DefaultHashBuilder::default())
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            map: HashMap::with_capacity(capacity),
+        }
+    }
+}
+
+impl<K, S> HashSet<K, S>
+where
+    K: Hash + PartialEq,
+{
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    pub fn iter(&self) -> Iter<'_, K> {
+        Iter(self.map.keys())
+    }
+
+    pub fn drain(&mut self) -> Drain<'_, K> {
+        Drain(self.map.drain())
+    }
+}
+
+impl<K, S> HashSet<K, S>
+where
+    K: Hash + PartialEq,
+    S: BuildHasher,
+{
+    pub fn with_capacity_and_hasher(capacity: usize, hash_builder: S) -> Self {
+        Self {
+            map: HashMap::with_capacity_and_hasher(capacity, hash_builder),
+        }
+    }
+
+    pub fn with_hasher(hash_builder: S) -> Self {
+        Self::with_capacity_and_hasher(0, hash_builder)
+    }
+
+    pub fn hasher(&self) -> &S {
+        self.map.hasher()
+    }
+
+    /// Returns whether `k` was newly added. An equal key that is already in
+    /// the set is kept as is.
+    pub fn insert(&mut self, k: K) -> bool {
+        if self.map.contains_key(&k) {
+            return false;
+        }
+        self.map.put(k, ());
+        true
+    }
+
+    pub fn contains<Q>(&self, k: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + PartialEq + ?Sized,
+    {
+        self.map.contains_key(k)
+    }
+
+    /// Returns whether `k` was in the set
+    pub fn remove<Q>(&mut self, k: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + PartialEq + ?Sized,
+    {
+        self.map.remove(k).is_some()
+    }
+
+    /// Keys in either set, each one once
+    pub fn union<'a>(&'a self, other: &'a HashSet<K, S>) -> Union<'a, K, S> {
+        let (larger, smaller) = if self.len() >= other.len() {
+            (self, other)
+        } else {
+            (other, self)
+        };
+        Union {
+            iter: larger.iter().chain(smaller.difference(larger)),
+        }
+    }
+
+    /// Keys in both sets
+    pub fn intersection<'a>(&'a self, other: &'a HashSet<K, S>) -> Intersection<'a, K, S> {
+        // walk the smaller one, look up in the larger one
+        let (larger, smaller) = if self.len() >= other.len() {
+            (self, other)
+        } else {
+            (other, self)
+        };
+        Intersection {
+            iter: smaller.iter(),
+            other: larger,
+        }
+    }
+
+    /// Keys in `self` but not in `other`
+    pub fn difference<'a>(&'a self, other: &'a HashSet<K, S>) -> Difference<'a, K, S> {
+        Difference {
+            iter: self.iter(),
+            other,
+        }
+    }
+
+    /// Keys in exactly one of the sets
+    pub fn symmetric_difference<'a>(
+        &'a self,
+        other: &'a HashSet<K, S>,
+    ) -> SymmetricDifference<'a, K, S> {
+        SymmetricDifference {
+            iter: self.difference(other).chain(other.difference(self)),
+        }
+    }
+
+    pub fn is_disjoint(&self, other: &HashSet<K, S>) -> bool {
+        self.intersection(other).next().is_none()
+    }
+
+    pub fn is_subset(&self, other: &HashSet<K, S>) -> bool {
+        self.len() <= other.len() && self.iter().all(|k| other.contains(k))
+    }
+
+    pub fn is_superset(&self, other: &HashSet<K, S>) -> bool {
+        other.is_subset(self)
+    }
+}
+
+impl<K, S> Default for HashSet<K, S>
+where
+    K: Hash + PartialEq,
+    S: BuildHasher + Default,
+{
+    fn default() -> Self {
+        Self::with_hasher(S::default())
+    }
+}
+
+pub struct Iter<'a, K>(crate::Keys<'a, K, ()>);
+
+impl<'a, K> Iterator for Iter<'a, K> {
+    type Item = &'a K;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+impl<K> Clone for Iter<'_, K> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+pub struct IntoIter<K>(crate::IntoIter<K, ()>);
+
+impl<K> Iterator for IntoIter<K> {
+    type Item = K;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(|(k, _)| k)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+pub struct Drain<'a, K>(crate::Drain<'a, K, ()>);
+
+impl<K> Iterator for Drain<'_, K> {
+    type Item = K;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(|(k, _)| k)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+impl<K> ExactSizeIterator for Iter<'_, K> {}
+impl<K> ExactSizeIterator for IntoIter<K> {}
+impl<K> ExactSizeIterator for Drain<'_, K> {}
+
+impl<K> FusedIterator for Iter<'_, K> {}
+impl<K> FusedIterator for IntoIter<K> {}
+impl<K> FusedIterator for Drain<'_, K> {}
+
+pub struct Difference<'a, K, S>
+where
+    K: Hash + PartialEq,
+{
+    iter: Iter<'a, K>,
+    other: &'a HashSet<K, S>,
+}
+
+impl<'a, K, S> Iterator for Difference<'a, K, S>
+where
+    K: Hash + PartialEq,
+    S: BuildHasher,
+{
+    type Item = &'a K;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.iter.find(|k| !self.other.contains(*k))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, self.iter.size_hint().1)
+    }
+}
+
+impl<K, S> Clone for Difference<'_, K, S>
+where
+    K: Hash + PartialEq,
+{
+    fn clone(&self) -> Self {
+        Self {
+            iter: self.iter.clone(),
+            other: self.other,
+        }
+    }
+}
+
+pub struct Intersection<'a, K, S>
+where
+    K: Hash + PartialEq,
+{
+    iter: Iter<'a, K>,
+    other: &'a HashSet<K, S>,
+}
+
+impl<'a, K, S> Iterator for Intersection<'a, K, S>
+where
+    K: Hash + PartialEq,
+    S: BuildHasher,
+{
+    type Item = &'a K;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.iter.find(|k| self.other.contains(*k))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, self.iter.size_hint().1)
+    }
+}
+
+pub struct Union<'a, K, S>
+where
+    K: Hash + PartialEq,
+{
+    iter: core::iter::Chain<Iter<'a, K>, Difference<'a, K, S>>,
+}
+
+impl<'a, K, S> Iterator for Union<'a, K, S>
+where
+    K: Hash + PartialEq,
+    S: BuildHasher,
+{
+    type Item = &'a K;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.iter.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.iter.size_hint()
+    }
+}
+
+pub struct SymmetricDifference<'a, K, S>
+where
+    K: Hash + PartialEq,
+{
+    iter: core::iter::Chain<Difference<'a, K, S>, Difference<'a, K, S>>,
+}
+
+impl<'a, K, S> Iterator for SymmetricDifference<'a, K, S>
+where
+    K: Hash + PartialEq,
+    S: BuildHasher,
+{
+    type Item = &'a K;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.iter.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.iter.size_hint()
+    }
+}
+
+impl<K: Hash + PartialEq, S: BuildHasher> FusedIterator for Difference<'_, K, S> {}
+impl<K: Hash + PartialEq, S: BuildHasher> FusedIterator for Intersection<'_, K, S> {}
+impl<K: Hash + PartialEq, S: BuildHasher> FusedIterator for Union<'_, K, S> {}
+impl<K: Hash + PartialEq, S: BuildHasher> FusedIterator for SymmetricDifference<'_, K, S> {}
+
+impl<'a, K: Hash + PartialEq, S> IntoIterator for &'a HashSet<K, S> {
+    type Item = &'a K;
+
+    type IntoIter = Iter<'a, K>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<K: Hash + PartialEq, S> IntoIterator for HashSet<K, S> {
+    type Item = K;
+
+    type IntoIter = IntoIter<K>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        IntoIter(self.map.into_iter())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{string::String, vec, vec::Vec};
+
+    use super::*;
+
+    fn set_of(keys: impl IntoIterator<Item = u32>) -> HashSet<u32> {
+        let mut set = HashSet::default();
+        for k in keys {
+            set.insert(k);
+        }
+        set
+    }
+
+    fn sorted<'a>(iter: impl Iterator<Item = &'a u32>) -> Vec<u32> {
+        let mut keys: Vec<_> = iter.copied().collect();
+        keys.sort();
+        keys
+    }
+
+    #[test]
+    fn insert_contains_remove() {
+        let mut set = HashSet::new();
+        assert!(set.insert(String::from("Foo")));
+        assert!(!set.insert(String::from("Foo")));
+        assert!(set.contains("Foo"));
+        assert_eq!(set.len(), 1);
+        assert!(set.remove("Foo"));
+        assert!(!set.remove("Foo"));
+        assert!(set.is_empty());
+    }
+
+    #[test]
+    fn algebra() {
+        let a = set_of(0..10);
+        let b = set_of(5..20);
+        assert_eq!(sorted(a.union(&b)), (0..20).collect::<Vec<_>>());
+        assert_eq!(sorted(b.union(&a)), (0..20).collect::<Vec<_>>());
+        assert_eq!(sorted(a.intersection(&b)), (5..10).collect::<Vec<_>>());
+        assert_eq!(sorted(b.intersection(&a)), (5..10).collect::<Vec<_>>());
+        assert_eq!(sorted(a.difference(&b)), (0..5).collect::<Vec<_>>());
+        assert_eq!(sorted(b.difference(&a)), (10..20).collect::<Vec<_>>());
+        assert_eq!(
+            sorted(a.symmetric_difference(&b)),
+            (0..5).chain(10..20).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn relations() {
+        let small = set_of(2..4);
+        let large = set_of(0..10);
+        let other = set_of(10..12);
+        assert!(small.is_subset(&large));
+        assert!(!large.is_subset(&small));
+        assert!(large.is_superset(&small));
+        assert!(large.is_disjoint(&other));
+        assert!(!large.is_disjoint(&small));
+        assert!(set_of([]).is_subset(&other));
+    }
+
+    #[test]
+    fn iteration() {
+        let mut set = set_of(0..100);
+        assert_eq!(sorted(set.iter()), (0..100).collect::<Vec<_>>());
+        assert_eq!(set.drain().count(), 100);
+        assert!(set.is_empty());
+        set.insert(7);
+        assert_eq!(set.into_iter().collect::<Vec<_>>(), vec![7]);
+    }
+}
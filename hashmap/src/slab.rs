@@ -0,0 +1,137 @@
+use alloc::vec::Vec;
+
+/// Stands in for a null pointer in the links between nodes
+pub(crate) const NIL: usize = usize::MAX;
+
+#[derive(Debug, Clone)]
+pub(crate) struct Node<T> {
+    pub(crate) item: T,
+    prev: usize,
+    next: usize,
+}
+
+/// Ends of one doubly linked list threaded through a [`Slab`]
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct List {
+    pub(crate) head: usize,
+    pub(crate) tail: usize,
+}
+
+impl Default for List {
+    fn default() -> Self {
+        Self {
+            head: NIL,
+            tail: NIL,
+        }
+    }
+}
+
+impl List {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.head == NIL
+    }
+
+    /// Follows a node that [`Slab::remove`] moved from `from` to `to`
+    pub(crate) fn relocate(&mut self, from: usize, to: usize) {
+        if self.head == from {
+            self.head = to;
+        }
+        if self.tail == from {
+            self.tail = to;
+        }
+    }
+}
+
+/// Dense storage for the nodes of any number of intrusive linked lists,
+/// addressed by index so no pointers are needed
+#[derive(Debug, Clone)]
+pub(crate) struct Slab<T> {
+    nodes: Vec<Node<T>>,
+}
+
+impl<T> Default for Slab<T> {
+    fn default() -> Self {
+        Self { nodes: Vec::new() }
+    }
+}
+
+impl<T> Slab<T> {
+    pub(crate) fn with_capacity(capacity: usize) -> Self {
+        Self {
+            nodes: Vec::with_capacity(capacity),
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub(crate) fn get(&self, index: usize) -> &T {
+        &self.nodes[index].item
+    }
+
+    pub(crate) fn get_mut(&mut self, index: usize) -> &mut T {
+        &mut self.nodes[index].item
+    }
+
+    pub(crate) fn next(&self, index: usize) -> usize {
+        self.nodes[index].next
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &T> {
+        self.nodes.iter().map(|node| &node.item)
+    }
+
+    /// Adds a node that isn't on any list yet
+    pub(crate) fn insert(&mut self, item: T) -> usize {
+        self.nodes.push(Node {
+            item,
+            prev: NIL,
+            next: NIL,
+        });
+        self.nodes.len() - 1
+    }
+
+    pub(crate) fn push_front(&mut self, list: &mut List, index: usize) {
+        self.nodes[index].prev = NIL;
+        self.nodes[index].next = list.head;
+        match list.head {
+            NIL => list.tail = index,
+            head => self.nodes[head].prev = index,
+        }
+        list.head = index;
+    }
+
+    pub(crate) fn unlink(&mut self, list: &mut List, index: usize) {
+        let Node { prev, next, .. } = self.nodes[index];
+        match prev {
+            NIL => list.head = next,
+            prev => self.nodes[prev].next = next,
+        }
+        match next {
+            NIL => list.tail = prev,
+            next => self.nodes[next].prev = prev,
+        }
+        self.nodes[index].prev = NIL;
+        self.nodes[index].next = NIL;
+    }
+
+    /// Removes an unlinked node by moving the last one into its place. If a
+    /// node was moved, returns the index it used to live at, and whoever
+    /// points at it (its list, a map) has to be told.
+    pub(crate) fn remove(&mut self, index: usize) -> (T, Option<usize>) {
+        let last = self.nodes.len() - 1;
+        let node = self.nodes.swap_remove(index);
+        if index == last {
+            return (node.item, None);
+        }
+        let Node { prev, next, .. } = self.nodes[index];
+        if prev != NIL {
+            self.nodes[prev].next = index;
+        }
+        if next != NIL {
+            self.nodes[next].prev = index;
+        }
+        (node.item, Some(last))
+    }
+}
@@ -0,0 +1,81 @@
+use alloc::collections::BTreeMap;
+use core::hash::BuildHasher;
+
+use crate::{Hash, HashMap, make_hash};
+
+/// A look at how well the entries of a [`HashMap`] are spread out.
+/// Long probes and long clusters mean the hash function is doing a poor job.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stats {
+    pub len: usize,
+    pub bucket_len: usize,
+    /// Entries over slots, tombstones not included
+    pub load_factor: f64,
+    /// Slots whose control byte is DELETED, left behind by removed entries
+    pub tombstones: usize,
+    /// Groups of control bytes looked at to find an entry, 1 being the best case
+    pub average_probe_length: f64,
+    pub max_probe_length: usize,
+    /// Maps the length of each run of used slots, tombstones included,
+    /// to how many runs that long there are
+    pub cluster_lengths: BTreeMap<usize, usize>,
+}
+
+impl<K, V, S> HashMap<K, V, S>
+where
+    K: Hash + PartialEq,
+    S: BuildHasher,
+{
+    /// Walks the whole bucket and rehashes every key, so this is as slow as a resize.
+    /// During an incremental resize only the new bucket is looked at, apart
+    /// from `len`.
+    pub fn stats(&self) -> Stats {
+        let bucket_len = self.bucket.len();
+
+        let mut probed = 0;
+        let mut total_probe_length = 0;
+        let mut max_probe_length = 0;
+        for (index, slot) in self.bucket.slots.iter().enumerate() {
+            if let Some((k, _)) = slot {
+                let length = self
+                    .bucket
+                    .probe_length(make_hash(&self.hash_builder, k), index);
+                probed += 1;
+                total_probe_length += length;
+                max_probe_length = max_probe_length.max(length);
+            }
+        }
+
+        let used = |index: usize| !self.bucket.is_empty_slot(index);
+        let mut cluster_lengths = BTreeMap::new();
+        // start right after an empty slot, so a cluster wrapping around the
+        // end of the bucket is counted once. There always is one.
+        if let Some(start) = (0..bucket_len).find(|index| !used(*index)) {
+            let mut length = 0;
+            for offset in 1..=bucket_len {
+                if used((start + offset) % bucket_len) {
+                    length += 1;
+                } else if length > 0 {
+                    *cluster_lengths.entry(length).or_default() += 1;
+                    length = 0;
+                }
+            }
+        }
+
+        Stats {
+            len: self.current_size,
+            bucket_len,
+            load_factor: probed as f64 / bucket_len as f64,
+            tombstones: (0..bucket_len)
+                .filter(|index| self.bucket.is_deleted_slot(*index))
+                .count(),
+            average_probe_length: if probed == 0 {
+                0.0
+            } else {
+                total_probe_length as f64 / probed as f64
+            },
+            max_probe_length,
+            cluster_lengths,
+        }
+    }
+}
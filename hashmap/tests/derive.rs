@@ -0,0 +1,136 @@
+use std::marker::PhantomData;
+
+use hashmap::{Hash, HashMap, HashSet};
+
+/// Not hashable as far as std is concerned
+#[derive(PartialEq, Debug)]
+struct Celsius(f64);
+
+impl Hash for Celsius {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.0.to_bits().hash(state)
+    }
+}
+
+#[derive(Hash, PartialEq, Debug)]
+struct Reading {
+    station: &'static str,
+    temperature: Celsius,
+}
+
+#[derive(Hash, PartialEq, Debug)]
+struct Pair<T>(T, T);
+
+#[derive(Hash, PartialEq, Debug)]
+enum Event {
+    Startup,
+    Reading(Reading),
+    Alarm { level: u8, at: Celsius },
+}
+
+#[derive(Hash, PartialEq)]
+enum Never {}
+
+#[derive(Hash, PartialEq)]
+struct Unit;
+
+#[derive(Hash, PartialEq)]
+struct Tagged<T> {
+    tag: u8,
+    _marker: PhantomData<T>,
+}
+
+#[test]
+fn derived_structs() {
+    let mut map = HashMap::new();
+    for i in 0..100 {
+        let reading = Reading {
+            station: "north",
+            temperature: Celsius(i as f64 / 2.0),
+        };
+        map.put(reading, i);
+    }
+    let probe = Reading {
+        station: "north",
+        temperature: Celsius(21.5),
+    };
+    assert_eq!(map.get(&probe), Some(&43));
+    assert_eq!(
+        map.get(&Reading {
+            station: "south",
+            temperature: Celsius(21.5),
+        }),
+        None
+    );
+
+    let mut pairs = HashSet::with_capacity(0);
+    assert!(pairs.insert(Pair(Celsius(1.0), Celsius(2.0))));
+    assert!(!pairs.insert(Pair(Celsius(1.0), Celsius(2.0))));
+    assert!(pairs.insert(Pair(Celsius(2.0), Celsius(1.0))));
+
+    let mut units = HashSet::with_capacity(0);
+    assert!(units.insert(Unit));
+    assert!(!units.insert(Unit));
+
+    let mut tagged = HashMap::new();
+    tagged.put(
+        Tagged::<Celsius> {
+            tag: 1,
+            _marker: PhantomData,
+        },
+        (),
+    );
+    assert!(tagged.contains_key(&Tagged {
+        tag: 1,
+        _marker: PhantomData
+    }));
+}
+
+#[test]
+fn derived_enums() {
+    let events = [
+        Event::Startup,
+        Event::Reading(Reading {
+            station: "north",
+            temperature: Celsius(1.0),
+        }),
+        Event::Alarm {
+            level: 3,
+            at: Celsius(90.0),
+        },
+        Event::Alarm {
+            level: 4,
+            at: Celsius(90.0),
+        },
+    ];
+    let mut map = HashMap::new();
+    for (i, event) in events.into_iter().enumerate() {
+        assert_eq!(map.put(event, i), None);
+    }
+    assert_eq!(map.len(), 4);
+    assert_eq!(map.get(&Event::Startup), Some(&0));
+    assert!(HashSet::<Never>::with_capacity(0).is_empty());
+    assert_eq!(
+        map.get(&Event::Alarm {
+            level: 4,
+            at: Celsius(90.0)
+        }),
+        Some(&3)
+    );
+}
+
+#[test]
+fn std_keys() {
+    // the blanket impl over std::hash::Hash covers everything std can hash
+    let mut map = HashMap::new();
+    map.put((1u32, -2i64, 'c', true), [1u8, 2, 3]);
+    assert_eq!(map[&(1, -2, 'c', true)], [1, 2, 3]);
+
+    let mut map = HashMap::new();
+    map.put(Some(vec![String::from("Foo")]), ());
+    assert!(map.contains_key(&Some(vec![String::from("Foo")])));
+
+    let mut map = HashMap::new();
+    map.put("Foo", 1i128);
+    assert_eq!(map.get("Foo"), Some(&1));
+}
@@ -0,0 +1,183 @@
+use std::time::Duration;
+
+/// What a class does about packets once it is full, or before it gets there
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum DropPolicy {
+    /// Arriving packets are dropped while the class is full
+    #[default]
+    TailDrop,
+    /// The front packet of the longest flow is dropped to make room for an
+    /// arriving one, so the oldest packet when flows share a queue
+    HeadDrop,
+    /// Random early detection. Arriving packets are dropped with a chance
+    /// rising from nothing at an average of `min_threshold` packets queued
+    /// to `max_probability` at `max_threshold`, and always past that. Every
+    /// arrival moves the average `weight` of the way to the current length.
+    Red {
+        min_threshold: usize,
+        max_threshold: usize,
+        max_probability: f64,
+        weight: f64,
+    },
+    /// Controlled delay. Once packets have spent at least `target` queued
+    /// for a whole `interval`, packets are dropped as they leave, more and
+    /// more often until the delay is back under `target`.
+    CoDel {
+        target: Duration,
+        interval: Duration,
+    },
+}
+
+/// What the drop policies need to remember about a class
+#[derive(Debug, Clone)]
+pub(crate) struct AqmState {
+    /// Average queue length for RED
+    average: f64,
+    rng: u64,
+    codel: CoDelState,
+}
+
+impl Default for AqmState {
+    fn default() -> Self {
+        Self {
+            average: 0.0,
+            // any seed but 0 works for xorshift
+            rng: 0x2545_f491_4f6c_dd1d,
+            codel: CoDelState::default(),
+        }
+    }
+}
+
+impl AqmState {
+    /// Whether RED drops a packet arriving when `len` are queued
+    pub(crate) fn red_drop(&mut self, policy: &DropPolicy, len: usize) -> bool {
+        let DropPolicy::Red {
+            min_threshold,
+            max_threshold,
+            max_probability,
+            weight,
+        } = *policy
+        else {
+            return false;
+        };
+        self.average += weight * (len as f64 - self.average);
+        if self.average < min_threshold as f64 {
+            false
+        } else if self.average >= max_threshold as f64 {
+            true
+        } else {
+            let probability = max_probability * (self.average - min_threshold as f64)
+                / (max_threshold - min_threshold) as f64;
+            self.random() < probability
+        }
+    }
+
+    /// xorshift64, in `[0, 1)`
+    fn random(&mut self) -> f64 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        (self.rng >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    pub(crate) fn codel(&mut self) -> &mut CoDelState {
+        &mut self.codel
+    }
+}
+
+/// CoDel as in RFC 8289
+#[derive(Debug, Clone, Default)]
+pub(crate) struct CoDelState {
+    /// When the delay will have been above target for an interval
+    first_above: Option<Duration>,
+    dropping: bool,
+    drop_next: Duration,
+    /// Drops since dropping started
+    count: u32,
+}
+
+impl CoDelState {
+    /// Whether the delay has been above `target` long enough to drop a
+    /// packet that waited `sojourn`. The last packet in a class is never
+    /// dropped, `more_queued` is whether there are any behind it.
+    fn ok_to_drop(
+        &mut self,
+        now: Duration,
+        sojourn: Duration,
+        more_queued: bool,
+        target: Duration,
+        interval: Duration,
+    ) -> bool {
+        if sojourn < target || !more_queued {
+            self.first_above = None;
+            return false;
+        }
+        match self.first_above {
+            None => {
+                self.first_above = Some(now + interval);
+                false
+            }
+            Some(first_above) => now >= first_above,
+        }
+    }
+
+    fn control_law(&self, from: Duration, interval: Duration) -> Duration {
+        from + interval.div_f64(f64::from(self.count).sqrt())
+    }
+
+    /// Decides the fate of the packets leaving the class. `pop` takes the
+    /// next packet out along with how long it waited and whether there are
+    /// more behind it, `drop` gets every packet CoDel drops. Returns the
+    /// first packet that isn't dropped.
+    pub(crate) fn dequeue<T>(
+        &mut self,
+        now: Duration,
+        target: Duration,
+        interval: Duration,
+        mut pop: impl FnMut() -> Option<(T, Duration, bool)>,
+        mut drop: impl FnMut(T),
+    ) -> Option<T> {
+        let Some((mut packet, sojourn, more_queued)) = pop() else {
+            self.first_above = None;
+            self.dropping = false;
+            return None;
+        };
+        let ok_to_drop = self.ok_to_drop(now, sojourn, more_queued, target, interval);
+        if self.dropping {
+            if !ok_to_drop {
+                self.dropping = false;
+            }
+            while self.dropping && now >= self.drop_next {
+                drop(packet);
+                self.count += 1;
+                let Some((next, sojourn, more_queued)) = pop() else {
+                    self.dropping = false;
+                    return None;
+                };
+                packet = next;
+                if self.ok_to_drop(now, sojourn, more_queued, target, interval) {
+                    self.drop_next = self.control_law(self.drop_next, interval);
+                } else {
+                    self.dropping = false;
+                }
+            }
+        } else if ok_to_drop {
+            drop(packet);
+            let Some((next, _, _)) = pop() else {
+                self.first_above = None;
+                return None;
+            };
+            packet = next;
+            self.dropping = true;
+            // pick up where the last bout of dropping left off if it was recent
+            let recent = now.saturating_sub(self.drop_next) < 16 * interval;
+            self.count = if self.count > 2 && recent {
+                self.count - 2
+            } else {
+                1
+            };
+            self.drop_next = self.control_law(now, interval);
+        }
+        Some(packet)
+    }
+}
@@ -0,0 +1,57 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Where a [`Scheduler`](crate::Scheduler) gets the time from
+pub trait Clock {
+    /// Time since some fixed point, which must never go backwards
+    fn now(&self) -> Duration;
+}
+
+/// The real time since the clock was made
+#[derive(Debug, Clone, Copy)]
+pub struct SystemClock {
+    start: Instant,
+}
+
+impl Default for SystemClock {
+    fn default() -> Self {
+        Self {
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        self.start.elapsed()
+    }
+}
+
+/// A clock that only moves when told to, for tests and simulations.
+/// Clones share the same time, so one can be kept to drive the one handed
+/// to a scheduler.
+#[derive(Debug, Clone, Default)]
+pub struct ManualClock {
+    nanos: Arc<AtomicU64>,
+}
+
+impl ManualClock {
+    /// Panics if `now` is earlier than the current time
+    pub fn set(&self, now: Duration) {
+        let nanos = now.as_nanos().try_into().expect("time fits in 584 years");
+        // never stores an earlier time, so the clock is left as it was on panic
+        let previous = self.nanos.fetch_max(nanos, Ordering::Relaxed);
+        assert!(previous <= nanos, "the clock can't go backwards");
+    }
+
+    pub fn advance(&self, by: Duration) {
+        self.set(self.now() + by);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Duration {
+        Duration::from_nanos(self.nanos.load(Ordering::Relaxed))
+    }
+}
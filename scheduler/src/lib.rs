@@ -1,83 +1,320 @@
-use std::collections::vec_deque;
-use std::iter::{Enumerate, Flatten, Skip, Take};
-use std::{
-    collections::{VecDeque, vec_deque::IterMut},
-    iter,
-};
+use std::time::Duration;
+
+use queue::{ClassQueue, FlowsIter, FlowsIterMut};
+use shaper::TokenBucket;
+
+mod aqm;
+mod clock;
+pub mod pcap;
+mod queue;
+mod shaper;
+pub mod sim;
+mod tree;
+
+pub use aqm::DropPolicy;
+pub use clock::{Clock, ManualClock, SystemClock};
+pub use queue::FlowMode;
+pub use shaper::RateLimit;
+pub use tree::{NodeId, TreeScheduler};
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Packet {
     class: u8,
     flow: u16,
     payload: u64,
+    /// Size on the wire in bytes
+    length: usize,
 }
 
+/// The most classes a scheduler can have, as many as a packet can name
+pub const MAX_CLASSES: usize = u8::MAX as usize + 1;
+
 impl Packet {
-    pub fn new(payload: u64, class: u8, flow: u16) -> Result<Self, String> {
-        if class > 7 {
-            Err(format!("Class cannot be greater than 7: Found {class}"))
-        } else {
-            Ok(Self {
-                payload,
-                class,
-                flow,
-            })
+    /// Whether `class` exists is up to the scheduler the packet is enqueued on
+    pub fn new(payload: u64, class: u8, flow: u16) -> Self {
+        Self {
+            payload,
+            class,
+            flow,
+            length: 0,
         }
     }
+
+    /// Packets are zero bytes long unless given a length. [`Mode::Deficit`]
+    /// charges them a byte all the same.
+    pub fn with_length(self, length: usize) -> Self {
+        Self { length, ..self }
+    }
+
+    pub fn length(&self) -> usize {
+        self.length
+    }
+}
+
+/// What a class's weight is counted in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Mode {
+    /// Weighted round robin, every round a class sends up to its weight in packets
+    #[default]
+    PacketCount,
+    /// Deficit round robin, every round a class earns its weight times
+    /// `quantum` in bytes and sends packets for as long as it can pay for
+    /// them. Whatever it doesn't spend carries over to the next round, as
+    /// long as it has packets waiting.
+    Deficit { quantum: usize },
+}
+
+/// How a class competes with the others
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ClassMode {
+    /// Shares the link with the other weighted classes by its weight
+    #[default]
+    Weighted,
+    /// Served ahead of every weighted class, and of strict classes with a
+    /// lower number. Its weight is ignored.
+    ///
+    /// With a `starvation_guard` of `n`, once `n` strict packets have gone
+    /// out in a row one weighted packet goes next, if there is one waiting.
+    Strict { starvation_guard: Option<usize> },
+}
+
+#[derive(Debug, Clone)]
+struct Config {
+    weights: Vec<usize>,
+    mode: Mode,
+    classes: Vec<ClassMode>,
+}
+
+impl Config {
+    fn is_strict(&self, class: usize) -> bool {
+        matches!(self.classes[class], ClassMode::Strict { .. })
+    }
 }
 
 #[derive(Debug, Clone)]
-pub struct Scheduler {
-    queues: [VecDeque<Packet>; 8],
-    weights: [usize; 8],
+pub struct Scheduler<C = SystemClock> {
+    queues: Vec<ClassQueue>,
+    config: Config,
+    /// Where [`dequeue`](Self::dequeue) is up to
+    round: Round,
+    /// The rate limit of each class, if it has one
+    buckets: Vec<Option<TokenBucket>>,
+    clock: C,
 }
 
 impl Default for Scheduler {
     fn default() -> Self {
+        // zero weight will drop the queue entirely
+        Self::new([1, 1, 1, 1, 1, 1, 1, 1])
+    }
+}
+
+impl Scheduler {
+    /// One class per weight
+    pub fn new(weights: impl Into<Vec<usize>>) -> Self {
+        Self::with_mode(weights, Mode::default())
+    }
+
+    pub fn with_mode(weights: impl Into<Vec<usize>>, mode: Mode) -> Self {
+        Self::with_clock(weights, mode, SystemClock::default())
+    }
+}
+
+impl<C: Clock> Scheduler<C> {
+    /// Panics if there are more than [`MAX_CLASSES`] weights, or if `mode`
+    /// is [`Mode::Deficit`] with a zero quantum, no class would ever earn
+    /// enough to send
+    pub fn with_clock(weights: impl Into<Vec<usize>>, mode: Mode, clock: C) -> Self {
+        let weights = weights.into();
+        assert!(weights.len() <= MAX_CLASSES, "too many classes");
+        assert_ne!(mode, Mode::Deficit { quantum: 0 }, "quantum must not be 0");
+        let config = Config {
+            classes: vec![ClassMode::default(); weights.len()],
+            weights,
+            mode,
+        };
         Self {
-            queues: Default::default(),
-            weights: [1, 1, 1, 1, 1, 1, 1, 1], // zero weight will drop the queue entirely
+            queues: vec![ClassQueue::default(); config.weights.len()],
+            buckets: vec![None; config.weights.len()],
+            round: Round::new(&config),
+            config,
+            clock,
+        }
+    }
+
+    /// Returns the packets dropped to keep classes within their capacity,
+    /// which may include ones queued earlier. Fails without enqueueing
+    /// anything if a packet's class doesn't exist.
+    pub fn enqueue(&mut self, packets: &[Packet]) -> Result<Vec<Packet>, String> {
+        if let Some(packet) = packets
+            .iter()
+            .find(|packet| packet.class as usize >= self.class_count())
+        {
+            return Err(format!(
+                "Class must be less than {}: Found {}",
+                self.class_count(),
+                packet.class
+            ));
+        }
+        let now = self.clock.now();
+        Ok(packets
+            .iter()
+            .filter_map(|packet| self.queues[packet.class as usize].push(*packet, now))
+            .collect())
+    }
+
+    /// Takes the next packet out, carrying on the round from the last one
+    /// however many packets have arrived since.
+    ///
+    /// Classes out of tokens are passed over, [`next_eligible`](Self::next_eligible)
+    /// tells when to try again if that leaves nothing to send.
+    pub fn dequeue(&mut self) -> Option<Packet> {
+        let now = self.clock.now();
+        for bucket in self.buckets.iter_mut().flatten() {
+            bucket.refill(now);
+        }
+        loop {
+            let (queues, buckets) = (&self.queues, &self.buckets);
+            let head = |class: usize| {
+                let Some(length) = queues[class].head_length() else {
+                    return Head::Empty;
+                };
+                match &buckets[class] {
+                    Some(bucket) if !bucket.allows(length) => Head::HeldBack,
+                    _ => Head::Ready(length),
+                }
+            };
+            let class = self.round.next_class(&self.config, head)?;
+            // CoDel may drop everything left in the class on the way out
+            if let Some(packet) = self.queues[class].pop(now) {
+                if let Some(bucket) = &mut self.buckets[class] {
+                    bucket.spend(packet.length());
+                }
+                return Some(packet);
+            }
         }
     }
+
+    /// Takes up to `max` packets out, as many calls to [`dequeue`](Self::dequeue) would
+    pub fn dequeue_batch(&mut self, max: usize) -> Vec<Packet> {
+        std::iter::from_fn(|| self.dequeue()).take(max).collect()
+    }
+
+    /// Caps how fast `class` sends, whatever the other classes are doing.
+    /// Every class starts out unlimited.
+    pub fn set_rate(&mut self, class: u8, limit: Option<RateLimit>) {
+        let now = self.clock.now();
+        self.buckets[class as usize] = limit.map(|limit| TokenBucket::new(limit, now));
+    }
 }
 
-impl Scheduler {
-    pub fn enqueue(&mut self, packets: &[Packet]) {
-        for packet in packets {
-            self.queues[packet.class as usize].push_back(*packet);
+impl<C> Scheduler<C> {
+    pub fn class_count(&self) -> usize {
+        self.queues.len()
+    }
+
+    /// Adds a weighted class after the existing ones and returns its number
+    pub fn add_class(&mut self, weight: usize) -> Result<u8, String> {
+        let class = self.class_count();
+        if class == MAX_CLASSES {
+            return Err(format!("Cannot have more than {MAX_CLASSES} classes"));
         }
+        self.queues.push(ClassQueue::default());
+        self.config.weights.push(weight);
+        self.config.classes.push(ClassMode::default());
+        self.round.credits.push(0);
+        self.buckets.push(None);
+        Ok(class as u8)
     }
 
-    pub fn new(weights: [usize; 8]) -> Self {
-        Self {
-            queues: Default::default(),
-            weights,
+    /// Removes the last class, handing back whatever was still queued in it
+    pub fn remove_class(&mut self) -> Option<Vec<Packet>> {
+        self.config.weights.pop()?;
+        self.config.classes.pop();
+        self.round.credits.pop();
+        self.buckets.pop();
+        if self.round.class == self.class_count() - 1 {
+            self.round = Round::new(&self.config);
         }
+        self.queues.pop().map(ClassQueue::into_vec)
+    }
+
+    pub fn set_weight(&mut self, class: u8, weight: usize) {
+        self.config.weights[class as usize] = weight;
+    }
+
+    /// Every class starts out [`ClassMode::Weighted`]
+    pub fn set_class_mode(&mut self, class: u8, mode: ClassMode) {
+        self.config.classes[class as usize] = mode;
+    }
+
+    /// Every class starts out [`FlowMode::Fifo`]. Packets already queued
+    /// are requeued under the new mode.
+    pub fn set_flow_mode(&mut self, class: u8, mode: FlowMode) {
+        self.queues[class as usize].set_mode(mode);
+    }
+
+    /// The most packets `class` will hold, every class starts out unbounded.
+    /// Lowering it below what's queued doesn't drop anything straight away.
+    pub fn set_capacity(&mut self, class: u8, capacity: Option<usize>) {
+        self.queues[class as usize].set_capacity(capacity);
+    }
+
+    /// Every class starts out [`DropPolicy::TailDrop`]
+    pub fn set_drop_policy(&mut self, class: u8, policy: DropPolicy) {
+        self.queues[class as usize].set_policy(policy);
+    }
+
+    /// Packets `class` has dropped so far, whatever the reason
+    pub fn drops(&self, class: u8) -> usize {
+        self.queues[class as usize].drops()
+    }
+
+    /// Packets waiting in `class`
+    pub fn queued(&self, class: u8) -> usize {
+        self.queues[class as usize].len()
+    }
+
+    pub fn weight(&self, class: u8) -> usize {
+        self.config.weights[class as usize]
+    }
+
+    pub fn clock(&self) -> &C {
+        &self.clock
+    }
+
+    /// When, on the scheduler's clock, the first packet held back by a rate
+    /// limit can go. None if no packet is waiting on one, or only on a
+    /// limit with a rate of 0.
+    pub fn next_eligible(&self) -> Option<Duration> {
+        self.buckets
+            .iter()
+            .zip(&self.queues)
+            .filter_map(|(bucket, queue)| bucket.as_ref()?.eligible_at(queue.head_length()?))
+            .min()
     }
 
+    /// The packets in the order [`dequeue`](Self::dequeue) would take them
+    /// out if nothing else arrived, leaving out what CoDel would drop and
+    /// as if no class were rate limited
     pub fn iter<'s>(&'s self) -> SchedulerIter<'s> {
         SchedulerIter {
-            scheduler: &self.queues,
-            weights: self.weights,
-            iterator: None,
-            index: 0,
+            queues: self.queues.iter().map(ClassQueue::iter).collect(),
+            config: &self.config,
+            round: self.round.clone(),
         }
     }
 
     pub fn iter_mut<'s>(&'s mut self) -> SchedulerIterMut<'s> {
         SchedulerIterMut {
-            scheduler: self
-                .queues
-                .iter_mut()
-                .map(|queue| queue.iter_mut())
-                .collect(),
-            buffer: Default::default(),
-            weights: self.weights,
+            round: self.round.clone(),
+            config: &self.config,
+            queues: self.queues.iter_mut().map(ClassQueue::iter_mut).collect(),
         }
     }
 }
 
-impl<'a> IntoIterator for &'a Scheduler {
+impl<'a, C> IntoIterator for &'a Scheduler<C> {
     type Item = &'a Packet;
 
     type IntoIter = SchedulerIter<'a>;
@@ -86,7 +323,7 @@ impl<'a> IntoIterator for &'a Scheduler {
         self.iter()
     }
 }
-impl<'a> IntoIterator for &'a mut Scheduler {
+impl<'a, C> IntoIterator for &'a mut Scheduler<C> {
     type Item = &'a mut Packet;
 
     type IntoIter = SchedulerIterMut<'a>;
@@ -96,123 +333,191 @@ impl<'a> IntoIterator for &'a mut Scheduler {
     }
 }
 
-impl IntoIterator for Scheduler {
+impl<C: Clock> IntoIterator for Scheduler<C> {
     type Item = Packet;
-    type IntoIter = SchedulerIntoIter;
+    type IntoIter = SchedulerIntoIter<C>;
     fn into_iter(self) -> Self::IntoIter {
-        Self::IntoIter {
-            scheduler: self,
-            buffer: Default::default(),
-        }
+        Self::IntoIter { scheduler: self }
     }
 }
 
-type PacketIter<'a> = Take<Skip<vec_deque::Iter<'a, Packet>>>;
+/// The front of a class, as a [`Round`] sees it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Head {
+    Empty,
+    /// Packets are waiting, but can't go yet
+    HeldBack,
+    /// A packet this long can go
+    Ready(usize),
+}
+
+impl From<Option<usize>> for Head {
+    fn from(length: Option<usize>) -> Self {
+        length.map_or(Head::Empty, Head::Ready)
+    }
+}
 
-struct CustomMap<'a, IT: Iterator<Item = (usize, &'a VecDeque<Packet>)>> {
-    it: IT,
-    weights: [usize; 8],
-    index: usize,
+/// Which class is being served and what every class may still send,
+/// carried from one packet to the next
+#[derive(Debug, Clone)]
+struct Round {
+    class: usize,
+    /// Packets or bytes depending on the [`Mode`]. Only the class being
+    /// served spends its credit, a deficit carries it over to its next turn.
+    credits: Vec<usize>,
+    /// Strict packets sent since the last weighted one
+    strict_streak: usize,
 }
 
-impl<'a, IT: Iterator<Item = (usize, &'a VecDeque<Packet>)>> Iterator for CustomMap<'a, IT> {
-    type Item = PacketIter<'a>;
+impl Round {
+    fn new(config: &Config) -> Self {
+        let mut round = Self {
+            class: 0,
+            credits: vec![0; config.weights.len()],
+            strict_streak: 0,
+        };
+        if !config.weights.is_empty() {
+            round.start_turn(config);
+        }
+        round
+    }
+
+    fn start_turn(&mut self, config: &Config) {
+        let class = self.class;
+        self.credits[class] = match config.mode {
+            Mode::PacketCount => config.weights[class],
+            Mode::Deficit { quantum } => self.credits[class] + config.weights[class] * quantum,
+        };
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        let (index, queue) = self.it.next()?;
-        Some(
-            queue
-                .iter()
-                .skip(self.weights[index] * self.index)
-                .take(self.weights[index]),
-        )
+    /// The class the next packet comes from. `head` gives what is at the
+    /// front of a class.
+    fn next_class(
+        &mut self,
+        config: &Config,
+        mut head: impl FnMut(usize) -> Head,
+    ) -> Option<usize> {
+        let strict = (0..config.classes.len())
+            .rev()
+            .find(|&class| config.is_strict(class) && matches!(head(class), Head::Ready(_)));
+        let Some(strict) = strict else {
+            self.strict_streak = 0;
+            return self.next_weighted(config, head);
+        };
+        let ClassMode::Strict { starvation_guard } = config.classes[strict] else {
+            unreachable!("only strict classes were searched")
+        };
+        if starvation_guard.is_some_and(|guard| self.strict_streak >= guard) {
+            let weighted = self.next_weighted(config, &mut head);
+            if weighted.is_some() {
+                self.strict_streak = 0;
+                return weighted;
+            }
+        }
+        self.strict_streak += 1;
+        Some(strict)
+    }
+
+    /// The next class in the round robin among the weighted classes. A
+    /// class held back keeps what it has saved, but doesn't earn any more
+    /// until it can send again.
+    fn next_weighted(
+        &mut self,
+        config: &Config,
+        mut head: impl FnMut(usize) -> Head,
+    ) -> Option<usize> {
+        let weights = &config.weights;
+        if weights.is_empty() {
+            return None;
+        }
+        let mut weighted_head = |class| {
+            if config.is_strict(class) {
+                Head::Empty
+            } else {
+                head(class)
+            }
+        };
+        // classes in a row that had nothing to send
+        let mut idle = 0;
+        loop {
+            let class = self.class;
+            let head = weighted_head(class);
+            let cost = match (head, config.mode) {
+                (Head::Ready(_), Mode::PacketCount) => Some(1),
+                // so packets without a length still take turns
+                (Head::Ready(length), Mode::Deficit { .. }) => Some(length.max(1)),
+                _ => None,
+            };
+            match cost {
+                Some(cost) if weights[class] > 0 && cost <= self.credits[class] => {
+                    self.credits[class] -= cost;
+                    return Some(class);
+                }
+                Some(_) if weights[class] > 0 => idle = 0,
+                _ => {
+                    // an empty class doesn't get to save up for later
+                    if head == Head::Empty {
+                        self.credits[class] = 0;
+                    }
+                    idle += 1;
+                    if idle == weights.len() {
+                        return None;
+                    }
+                }
+            }
+            self.class = (class + 1) % weights.len();
+            if weighted_head(self.class) != Head::HeldBack {
+                self.start_turn(config);
+            }
+        }
     }
 }
 
 pub struct SchedulerIter<'a> {
-    scheduler: &'a [VecDeque<Packet>],
-    iterator: Option<Flatten<CustomMap<'a, Enumerate<std::slice::Iter<'a, VecDeque<Packet>>>>>>,
-    weights: [usize; 8],
-    index: usize,
+    queues: Vec<FlowsIter<'a>>,
+    config: &'a Config,
+    round: Round,
 }
 
 impl<'a> Iterator for SchedulerIter<'a> {
     type Item = &'a Packet;
 
     fn next(&mut self) -> Option<Self::Item> {
-        match self.iterator.iter_mut().flatten().next() {
-            None => {
-                let enumerated = self.scheduler.iter().enumerate();
-
-                let mapped = CustomMap {
-                    it: enumerated,
-                    index: self.index,
-                    weights: self.weights,
-                };
-
-                self.iterator = Some(mapped.flatten());
-
-                self.index += 1;
-
-                self.iterator.iter_mut().flatten().next()
-            }
-            Some(value) => Some(value),
-        }
+        let queues = &mut self.queues;
+        let class = self
+            .round
+            .next_class(self.config, |class| queues[class].head_length().into())?;
+        queues[class].take()
     }
 }
+
 pub struct SchedulerIterMut<'a> {
-    scheduler: Vec<IterMut<'a, Packet>>,
-    buffer: VecDeque<&'a mut Packet>,
-    weights: [usize; 8],
+    queues: Vec<FlowsIterMut<'a>>,
+    config: &'a Config,
+    round: Round,
 }
 
 impl<'a> Iterator for SchedulerIterMut<'a> {
     type Item = &'a mut Packet;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.buffer.is_empty() {
-            self.buffer.append(
-                &mut self
-                    .scheduler
-                    .iter_mut()
-                    .enumerate()
-                    .flat_map(|(index, queue)| {
-                        iter::repeat_with(|| queue.next())
-                            .take(self.weights[index])
-                            .flatten()
-                    })
-                    .collect(),
-            );
-        }
-        self.buffer.pop_front()
+        let queues = &mut self.queues;
+        let class = self
+            .round
+            .next_class(self.config, |class| queues[class].head_length().into())?;
+        queues[class].take()
     }
 }
 
-pub struct SchedulerIntoIter {
-    scheduler: Scheduler,
-    buffer: VecDeque<Packet>,
+pub struct SchedulerIntoIter<C = SystemClock> {
+    scheduler: Scheduler<C>,
 }
 
-impl Iterator for SchedulerIntoIter {
+impl<C: Clock> Iterator for SchedulerIntoIter<C> {
     type Item = Packet;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let queues = &mut self.scheduler.queues;
-        let weights = self.scheduler.weights;
-        if self.buffer.is_empty() {
-            self.buffer.append(
-                &mut queues
-                    .iter_mut()
-                    .enumerate()
-                    .flat_map(|(index, queue)| {
-                        iter::repeat_with(|| queue.pop_front())
-                            .take(weights[index])
-                            .flatten()
-                    })
-                    .collect(),
-            );
-        }
-        self.buffer.pop_front()
+        self.scheduler.dequeue()
     }
 }
 
@@ -0,0 +1,199 @@
+use std::{
+    fs::{self, File},
+    io::BufReader,
+    num::{NonZeroU64, NonZeroUsize},
+    path::PathBuf,
+    process::ExitCode,
+    time::Duration,
+};
+
+use clap::Parser;
+use scheduler::{
+    ClassMode, DropPolicy, FlowMode, MAX_CLASSES, ManualClock, Mode, RateLimit, Scheduler,
+    pcap::read_pcap,
+    sim::{Report, read_csv, simulate},
+};
+
+/// Replays a packet trace through a scheduler and reports how every class
+/// and flow fared
+#[derive(Parser, Debug)]
+struct Args {
+    /// CSV of time in seconds, class, flow and size in bytes, or a .pcap or
+    /// .pcapng capture classed by the top three bits of the DSCP
+    trace: PathBuf,
+    /// Link speed in bytes per second
+    #[arg(long, default_value = "125000")]
+    link_rate: NonZeroU64,
+    /// One weight per class
+    #[arg(long, value_delimiter = ',', default_value = "1,1,1,1,1,1,1,1")]
+    weights: Vec<usize>,
+    /// Weigh classes in bytes with deficit round robin, earning this many
+    /// bytes per unit of weight every round
+    #[arg(long)]
+    quantum: Option<NonZeroUsize>,
+    /// Serve a class with strict priority, as CLASS or CLASS:STARVATION_GUARD
+    #[arg(long, value_parser = parse_strict)]
+    strict: Vec<(u8, ClassMode)>,
+    /// Queue flows within every class as fifo, rr or sfq:BUCKETS
+    #[arg(long, value_parser = parse_flow_mode, default_value = "fifo")]
+    flows: FlowMode,
+    /// Packets every class can hold
+    #[arg(long)]
+    capacity: Option<usize>,
+    /// What every class drops, as tail, head, red:MIN:MAX:PROBABILITY:WEIGHT
+    /// or codel:TARGET_MS:INTERVAL_MS
+    #[arg(long, value_parser = parse_drop_policy, default_value = "tail")]
+    drop_policy: DropPolicy,
+    /// Rate limit a class, as CLASS:BYTES_PER_SECOND:BURST_BYTES
+    #[arg(long, value_parser = parse_rate)]
+    rate: Vec<(u8, RateLimit)>,
+}
+
+fn fields<const N: usize>(arg: &str) -> Result<[&str; N], String> {
+    arg.split(':')
+        .collect::<Vec<_>>()
+        .try_into()
+        .map_err(|_| format!("expected {N} fields separated by ':' in {arg:?}"))
+}
+
+fn number<T: std::str::FromStr>(field: &str) -> Result<T, String> {
+    field
+        .parse()
+        .map_err(|_| format!("{field:?} is not a number"))
+}
+
+/// For the `NonZero` integers
+fn positive<T: std::str::FromStr>(field: &str) -> Result<T, String> {
+    field
+        .parse()
+        .map_err(|_| format!("{field:?} is not a number above 0"))
+}
+
+fn parse_strict(arg: &str) -> Result<(u8, ClassMode), String> {
+    let (class, starvation_guard) = match arg.split_once(':') {
+        Some((class, guard)) => (class, Some(number(guard)?)),
+        None => (arg, None),
+    };
+    Ok((number(class)?, ClassMode::Strict { starvation_guard }))
+}
+
+fn parse_flow_mode(arg: &str) -> Result<FlowMode, String> {
+    match arg.split_once(':') {
+        None if arg == "fifo" => Ok(FlowMode::Fifo),
+        None if arg == "rr" => Ok(FlowMode::RoundRobin),
+        Some(("sfq", buckets)) => Ok(FlowMode::Stochastic {
+            buckets: positive::<NonZeroUsize>(buckets)?.get(),
+            perturbation: 0,
+        }),
+        _ => Err(format!("unknown flow mode {arg:?}")),
+    }
+}
+
+fn parse_drop_policy(arg: &str) -> Result<DropPolicy, String> {
+    let millis = |field| number(field).map(Duration::from_millis);
+    match arg.split_once(':').map_or(arg, |(name, _)| name) {
+        "tail" => Ok(DropPolicy::TailDrop),
+        "head" => Ok(DropPolicy::HeadDrop),
+        "red" => {
+            let [_, min, max, probability, weight] = fields(arg)?;
+            let (min_threshold, max_threshold) = (number(min)?, number(max)?);
+            let (max_probability, weight) = (number(probability)?, number(weight)?);
+            if min_threshold >= max_threshold {
+                Err(format!("the thresholds are out of order in {arg:?}"))
+            } else if !(0.0..=1.0).contains(&max_probability) {
+                Err(format!("{probability:?} is not a probability"))
+            } else if !(weight > 0.0 && weight <= 1.0) {
+                Err(format!("the weight {weight} is not above 0 and at most 1"))
+            } else {
+                Ok(DropPolicy::Red {
+                    min_threshold,
+                    max_threshold,
+                    max_probability,
+                    weight,
+                })
+            }
+        }
+        "codel" => {
+            let [_, target, interval] = fields(arg)?;
+            Ok(DropPolicy::CoDel {
+                target: millis(target)?,
+                interval: Duration::from_millis(positive::<NonZeroU64>(interval)?.get()),
+            })
+        }
+        _ => Err(format!("unknown drop policy {arg:?}")),
+    }
+}
+
+fn parse_rate(arg: &str) -> Result<(u8, RateLimit), String> {
+    let [class, rate, burst] = fields(arg)?;
+    Ok((
+        number(class)?,
+        RateLimit {
+            rate: number(rate)?,
+            burst: number(burst)?,
+        },
+    ))
+}
+
+fn run(args: Args) -> Result<Report, String> {
+    if args.weights.len() > MAX_CLASSES {
+        return Err(format!(
+            "There can be at most {MAX_CLASSES} classes: Found {}",
+            args.weights.len()
+        ));
+    }
+    let mode = match args.quantum {
+        Some(quantum) => Mode::Deficit {
+            quantum: quantum.get(),
+        },
+        None => Mode::PacketCount,
+    };
+    let mut scheduler = Scheduler::with_clock(args.weights, mode, ManualClock::default());
+    let class_count = scheduler.class_count();
+    // a u8 range can't reach 256, the most classes there can be
+    for class in (0..=u8::MAX).take(class_count) {
+        scheduler.set_flow_mode(class, args.flows);
+        scheduler.set_capacity(class, args.capacity);
+        scheduler.set_drop_policy(class, args.drop_policy);
+    }
+    let check = |class: u8| {
+        if class as usize >= class_count {
+            Err(format!(
+                "There are only {class_count} classes: Found {class}"
+            ))
+        } else {
+            Ok(class)
+        }
+    };
+    for (class, mode) in args.strict {
+        scheduler.set_class_mode(check(class)?, mode);
+    }
+    for (class, limit) in args.rate {
+        scheduler.set_rate(check(class)?, Some(limit));
+    }
+
+    let open_error = |error| format!("{}: {error}", args.trace.display());
+    let capture = args
+        .trace
+        .extension()
+        .is_some_and(|extension| extension == "pcap" || extension == "pcapng");
+    let arrivals = if capture {
+        read_pcap(&fs::read(&args.trace).map_err(open_error)?)?
+    } else {
+        read_csv(BufReader::new(File::open(&args.trace).map_err(open_error)?))?
+    };
+    simulate(scheduler, &arrivals, args.link_rate.get())
+}
+
+fn main() -> ExitCode {
+    match run(Args::parse()) {
+        Ok(report) => {
+            println!("{report}");
+            ExitCode::SUCCESS
+        }
+        Err(error) => {
+            eprintln!("{error}");
+            ExitCode::FAILURE
+        }
+    }
+}
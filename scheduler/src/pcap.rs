@@ -0,0 +1,316 @@
+//! Reading pcap and pcapng captures as traces for [`sim`](crate::sim)
+
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::time::Duration;
+
+use crate::sim::Arrival;
+
+const ETHERNET: u32 = 1;
+/// Raw IPv4 or IPv6, with no link layer
+const RAW_IP: u32 = 101;
+
+/// Reads the IPv4 and IPv6 frames of a pcap or pcapng capture, either byte
+/// order, from Ethernet (VLAN tagged or not) or raw IP links. Other frames
+/// are skipped.
+///
+/// An arrival's time is since the first frame in the capture and its size
+/// is how long the frame was on the wire, even if less was captured. Its
+/// class is the top three bits of the DSCP, the class selector, so there
+/// are 8 of them. Its flow is a hash of the source and destination
+/// addresses, the protocol and the ports where it has them. The arrivals
+/// come back in order of time.
+pub fn read_pcap(bytes: &[u8]) -> Result<Vec<Arrival>, String> {
+    let frames = match bytes.get(..4) {
+        Some([0x0a, 0x0d, 0x0d, 0x0a]) => read_pcapng(bytes)?,
+        Some(_) => read_classic(bytes)?,
+        None => return Err("Not a pcap or pcapng capture".to_string()),
+    };
+    let start = frames
+        .iter()
+        .map(|frame| frame.time)
+        .min()
+        .unwrap_or_default();
+    let mut arrivals: Vec<_> = frames
+        .into_iter()
+        .filter_map(|frame| {
+            let header = match frame.link {
+                ETHERNET => ethernet(frame.data)?,
+                RAW_IP => ip(frame.data)?,
+                _ => return None,
+            };
+            Some(Arrival {
+                time: frame.time - start,
+                class: header.dscp >> 3,
+                flow: header.flow(),
+                size: frame.length,
+            })
+        })
+        .collect();
+    arrivals.sort_by_key(|arrival| arrival.time);
+    Ok(arrivals)
+}
+
+/// A frame as captured
+struct Frame<'a> {
+    /// Since the epoch
+    time: Duration,
+    /// The link type of the interface it was captured on
+    link: u32,
+    /// On the wire, `data` may be cut short
+    length: usize,
+    data: &'a [u8],
+}
+
+/// Reads integers in the byte order of a capture
+#[derive(Clone, Copy)]
+struct Endian {
+    big: bool,
+}
+
+impl Endian {
+    fn u16(self, bytes: &[u8], at: usize) -> Option<u16> {
+        let bytes = bytes.get(at..at + 2)?.try_into().ok()?;
+        Some(if self.big {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        })
+    }
+
+    fn u32(self, bytes: &[u8], at: usize) -> Option<u32> {
+        let bytes = bytes.get(at..at + 4)?.try_into().ok()?;
+        Some(if self.big {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        })
+    }
+}
+
+fn truncated() -> String {
+    "The capture is cut short".to_string()
+}
+
+fn read_classic(bytes: &[u8]) -> Result<Vec<Frame<'_>>, String> {
+    let magic = Endian { big: true }.u32(bytes, 0).ok_or_else(truncated)?;
+    let (endian, nanos) = match magic {
+        0xa1b2_c3d4 => (Endian { big: true }, false),
+        0xd4c3_b2a1 => (Endian { big: false }, false),
+        0xa1b2_3c4d => (Endian { big: true }, true),
+        0x4d3c_b2a1 => (Endian { big: false }, true),
+        _ => return Err("Not a pcap or pcapng capture".to_string()),
+    };
+    // the top bits can hold the FCS length
+    let link = endian.u32(bytes, 20).ok_or_else(truncated)? & 0x0fff_ffff;
+
+    let mut frames = Vec::new();
+    let mut at = 24;
+    while at < bytes.len() {
+        let field = |offset| endian.u32(bytes, at + offset).ok_or_else(truncated);
+        let (seconds, fraction) = (field(0)?, field(4)?);
+        let (captured, length) = (field(8)? as usize, field(12)? as usize);
+        let data = bytes
+            .get(at + 16..at + 16 + captured)
+            .ok_or_else(truncated)?;
+        let fraction = if nanos {
+            Duration::from_nanos(fraction.into())
+        } else {
+            Duration::from_micros(fraction.into())
+        };
+        frames.push(Frame {
+            time: Duration::from_secs(seconds.into()) + fraction,
+            link,
+            length,
+            data,
+        });
+        at += 16 + captured;
+    }
+    Ok(frames)
+}
+
+/// An interface frames are captured on, in pcapng
+struct Interface {
+    link: u32,
+    /// Timestamps count in these
+    ticks_per_second: u128,
+}
+
+impl Interface {
+    fn new(endian: Endian, body: &[u8]) -> Option<Self> {
+        let mut ticks_per_second = 1_000_000;
+        // options follow the link type, reserved bytes and snap length
+        let mut at = 8;
+        while let (Some(code), Some(length)) = (endian.u16(body, at), endian.u16(body, at + 2)) {
+            let length = length as usize;
+            // if_tsresol, a negative power of 10, or of 2 with the top bit set
+            if code == 9 && length == 1 {
+                let resolution = *body.get(at + 4)?;
+                let base: u128 = if resolution & 0x80 == 0 { 10 } else { 2 };
+                ticks_per_second = base.checked_pow(u32::from(resolution & 0x7f))?;
+            } else if code == 0 {
+                break;
+            }
+            // options are padded to 32 bits
+            at += 4 + length.next_multiple_of(4);
+        }
+        Some(Self {
+            link: endian.u16(body, 0)?.into(),
+            ticks_per_second,
+        })
+    }
+
+    fn time(&self, ticks: u64) -> Duration {
+        let ticks = u128::from(ticks);
+        let seconds = ticks / self.ticks_per_second;
+        let nanos = ticks % self.ticks_per_second * 1_000_000_000 / self.ticks_per_second;
+        Duration::from_secs(seconds as u64) + Duration::from_nanos(nanos as u64)
+    }
+}
+
+fn read_pcapng(bytes: &[u8]) -> Result<Vec<Frame<'_>>, String> {
+    const SECTION: u32 = 0x0a0d_0d0a;
+    const INTERFACE: u32 = 1;
+    const ENHANCED_PACKET: u32 = 6;
+
+    let mut endian = Endian { big: false };
+    let mut interfaces = Vec::new();
+    let mut frames = Vec::new();
+    let mut at = 0;
+    while at < bytes.len() {
+        // the section header's type reads the same in either byte order,
+        // its byte order magic says which the section is in
+        let kind = endian.u32(bytes, at).ok_or_else(truncated)?;
+        if kind == SECTION {
+            endian = match bytes.get(at + 8..at + 12) {
+                Some([0x1a, 0x2b, 0x3c, 0x4d]) => Endian { big: true },
+                Some([0x4d, 0x3c, 0x2b, 0x1a]) => Endian { big: false },
+                _ => return Err("Not a pcap or pcapng capture".to_string()),
+            };
+            // interfaces are numbered within a section
+            interfaces.clear();
+        }
+        let length = endian.u32(bytes, at + 4).ok_or_else(truncated)? as usize;
+        if length < 12 {
+            return Err(format!("A block at byte {at} is too short"));
+        }
+        let body = bytes.get(at + 8..at + length - 4).ok_or_else(truncated)?;
+        match kind {
+            INTERFACE => interfaces.push(Interface::new(endian, body).ok_or_else(truncated)?),
+            ENHANCED_PACKET => {
+                let field = |offset| endian.u32(body, offset).ok_or_else(truncated);
+                let interface = interfaces
+                    .get(field(0)? as usize)
+                    .ok_or_else(|| format!("A packet at byte {at} has no interface"))?;
+                let ticks = u64::from(field(4)?) << 32 | u64::from(field(8)?);
+                let captured = field(12)? as usize;
+                frames.push(Frame {
+                    time: interface.time(ticks),
+                    link: interface.link,
+                    length: field(16)? as usize,
+                    data: body.get(20..20 + captured).ok_or_else(truncated)?,
+                });
+            }
+            _ => {}
+        }
+        at += length;
+    }
+    Ok(frames)
+}
+
+/// What the scheduler needs from a packet's headers
+#[derive(Debug, PartialEq)]
+struct Header {
+    dscp: u8,
+    source: IpAddr,
+    destination: IpAddr,
+    protocol: u8,
+    /// Source and destination, if the protocol has them
+    ports: Option<(u16, u16)>,
+}
+
+impl Header {
+    fn flow(&self) -> u16 {
+        let mut hasher = DefaultHasher::new();
+        (self.source, self.destination, self.protocol, self.ports).hash(&mut hasher);
+        hasher.finish() as u16
+    }
+}
+
+fn ethernet(frame: &[u8]) -> Option<Header> {
+    let mut at = 12;
+    loop {
+        let kind = u16::from_be_bytes(frame.get(at..at + 2)?.try_into().ok()?);
+        at += 2;
+        match kind {
+            // VLAN tags, possibly stacked
+            0x8100 | 0x88a8 => at += 2,
+            0x0800 | 0x86dd => return ip(&frame[at..]),
+            _ => return None,
+        }
+    }
+}
+
+fn ip(packet: &[u8]) -> Option<Header> {
+    match packet.first()? >> 4 {
+        4 => ipv4(packet),
+        6 => ipv6(packet),
+        _ => None,
+    }
+}
+
+fn ipv4(packet: &[u8]) -> Option<Header> {
+    let header_length = usize::from(packet.first()? & 0x0f) * 4;
+    let address = |at: usize| -> Option<Ipv4Addr> {
+        Some(<[u8; 4]>::try_from(packet.get(at..at + 4)?).ok()?.into())
+    };
+    let protocol = *packet.get(9)?;
+    // only the first fragment has the ports
+    let fragment_offset = u16::from_be_bytes(packet.get(6..8)?.try_into().ok()?) & 0x1fff;
+    Some(Header {
+        dscp: packet.get(1)? >> 2,
+        source: address(12)?.into(),
+        destination: address(16)?.into(),
+        protocol,
+        ports: if fragment_offset == 0 {
+            ports(protocol, packet.get(header_length..)?)
+        } else {
+            None
+        },
+    })
+}
+
+fn ipv6(packet: &[u8]) -> Option<Header> {
+    let address = |at: usize| -> Option<Ipv6Addr> {
+        Some(<[u8; 16]>::try_from(packet.get(at..at + 16)?).ok()?.into())
+    };
+    // the traffic class straddles the first two bytes
+    let traffic_class = (packet.first()? << 4) | (packet.get(1)? >> 4);
+    let mut protocol = *packet.get(6)?;
+    let mut at = 40;
+    // skip hop by hop, routing and destination options to the transport header
+    while matches!(protocol, 0 | 43 | 60) {
+        protocol = *packet.get(at)?;
+        at += (usize::from(*packet.get(at + 1)?) + 1) * 8;
+    }
+    Some(Header {
+        dscp: traffic_class >> 2,
+        source: address(8)?.into(),
+        destination: address(24)?.into(),
+        protocol,
+        ports: ports(protocol, packet.get(at..).unwrap_or_default()),
+    })
+}
+
+/// TCP, UDP and SCTP all start with the ports
+fn ports(protocol: u8, transport: &[u8]) -> Option<(u16, u16)> {
+    if !matches!(protocol, 6 | 17 | 132) {
+        return None;
+    }
+    let port = |at: usize| {
+        Some(u16::from_be_bytes(
+            transport.get(at..at + 2)?.try_into().ok()?,
+        ))
+    };
+    Some((port(0)?, port(2)?))
+}
@@ -0,0 +1,353 @@
+use std::collections::{HashMap, VecDeque, vec_deque};
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::iter::Peekable;
+use std::time::Duration;
+
+use crate::Packet;
+use crate::aqm::{AqmState, DropPolicy};
+
+/// How the flows within a class share it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FlowMode {
+    /// Every flow shares one queue, in order of arrival
+    #[default]
+    Fifo,
+    /// Every flow gets a queue of its own and they take turns, a packet at a time
+    RoundRobin,
+    /// Stochastic fair queuing, flows are hashed into `buckets` queues that
+    /// take turns, so only flows that collide share a queue. A different
+    /// `perturbation` makes different flows collide.
+    Stochastic { buckets: usize, perturbation: u64 },
+}
+
+impl FlowMode {
+    /// Flows with the same key share a queue
+    fn key(self, flow: u16) -> u64 {
+        match self {
+            Self::Fifo => 0,
+            Self::RoundRobin => flow.into(),
+            Self::Stochastic {
+                buckets,
+                perturbation,
+            } => {
+                let mut hasher = DefaultHasher::new();
+                (perturbation, flow).hash(&mut hasher);
+                hasher.finish() % buckets as u64
+            }
+        }
+    }
+}
+
+/// A packet waiting in a class
+#[derive(Debug, Clone)]
+pub(crate) struct Queued {
+    packet: Packet,
+    enqueued: Duration,
+}
+
+/// Something packets come out of in order, one flow's worth of a class
+pub(crate) trait FlowQueue {
+    type Item;
+
+    fn head_length(&mut self) -> Option<usize>;
+
+    fn take(&mut self) -> Option<Self::Item>;
+}
+
+impl FlowQueue for VecDeque<Queued> {
+    type Item = Queued;
+
+    fn head_length(&mut self) -> Option<usize> {
+        self.front().map(|queued| queued.packet.length())
+    }
+
+    fn take(&mut self) -> Option<Self::Item> {
+        self.pop_front()
+    }
+}
+
+impl<'a> FlowQueue for Peekable<vec_deque::Iter<'a, Queued>> {
+    type Item = &'a Packet;
+
+    fn head_length(&mut self) -> Option<usize> {
+        self.peek().map(|queued| queued.packet.length())
+    }
+
+    fn take(&mut self) -> Option<Self::Item> {
+        self.next().map(|queued| &queued.packet)
+    }
+}
+
+impl<'a> FlowQueue for Peekable<vec_deque::IterMut<'a, Queued>> {
+    type Item = &'a mut Packet;
+
+    fn head_length(&mut self) -> Option<usize> {
+        self.peek().map(|queued| queued.packet.length())
+    }
+
+    fn take(&mut self) -> Option<Self::Item> {
+        self.next().map(|queued| &mut queued.packet)
+    }
+}
+
+/// The flow queues of a class taking turns
+#[derive(Debug, Clone)]
+pub(crate) struct Flows<Q> {
+    queues: Vec<Q>,
+    /// Queues with something in them, the front one goes next
+    active: VecDeque<usize>,
+}
+
+impl<Q: FlowQueue> Flows<Q> {
+    pub(crate) fn head_length(&mut self) -> Option<usize> {
+        let &queue = self.active.front()?;
+        self.queues[queue].head_length()
+    }
+
+    /// Takes the next packet and passes the turn on, along with which queue
+    /// it came out of
+    fn take_from(&mut self) -> Option<(usize, Q::Item)> {
+        let queue = self.active.pop_front()?;
+        let item = self.queues[queue]
+            .take()
+            .expect("active queues aren't empty");
+        if self.queues[queue].head_length().is_some() {
+            self.active.push_back(queue);
+        }
+        Some((queue, item))
+    }
+
+    pub(crate) fn take(&mut self) -> Option<Q::Item> {
+        self.take_from().map(|(_, item)| item)
+    }
+}
+
+pub(crate) type FlowsIter<'a> = Flows<Peekable<vec_deque::Iter<'a, Queued>>>;
+pub(crate) type FlowsIterMut<'a> = Flows<Peekable<vec_deque::IterMut<'a, Queued>>>;
+
+/// The packets of one class, split up by flow
+#[derive(Debug, Clone)]
+pub(crate) struct ClassQueue {
+    mode: FlowMode,
+    flows: Flows<VecDeque<Queued>>,
+    /// The queue each flow key is using
+    queue_of: HashMap<u64, usize>,
+    /// The key each queue is being used for, so it can be freed once empty
+    keys: Vec<u64>,
+    /// Queues no key is using
+    free: Vec<usize>,
+    len: usize,
+    capacity: Option<usize>,
+    policy: DropPolicy,
+    aqm: AqmState,
+    drops: usize,
+}
+
+impl Default for ClassQueue {
+    fn default() -> Self {
+        Self {
+            mode: FlowMode::default(),
+            flows: Flows {
+                queues: Vec::new(),
+                active: VecDeque::new(),
+            },
+            queue_of: HashMap::new(),
+            keys: Vec::new(),
+            free: Vec::new(),
+            len: 0,
+            capacity: None,
+            policy: DropPolicy::default(),
+            aqm: AqmState::default(),
+            drops: 0,
+        }
+    }
+}
+
+impl ClassQueue {
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    pub(crate) fn drops(&self) -> usize {
+        self.drops
+    }
+
+    pub(crate) fn set_capacity(&mut self, capacity: Option<usize>) {
+        self.capacity = capacity;
+    }
+
+    pub(crate) fn set_policy(&mut self, policy: DropPolicy) {
+        match policy {
+            DropPolicy::Red {
+                min_threshold,
+                max_threshold,
+                max_probability,
+                weight,
+            } => {
+                assert!(min_threshold < max_threshold, "thresholds out of order");
+                assert!((0.0..=1.0).contains(&max_probability), "not a probability");
+                assert!(weight > 0.0 && weight <= 1.0, "weight out of range");
+            }
+            DropPolicy::CoDel { interval, .. } => {
+                assert!(!interval.is_zero(), "interval must not be 0");
+            }
+            DropPolicy::TailDrop | DropPolicy::HeadDrop => {}
+        }
+        self.policy = policy;
+    }
+
+    /// Queues `packet` if the drop policy lets it in. Returns the packet
+    /// dropped instead, if any.
+    pub(crate) fn push(&mut self, packet: Packet, now: Duration) -> Option<Packet> {
+        if self.aqm.red_drop(&self.policy, self.len) {
+            self.drops += 1;
+            return Some(packet);
+        }
+        let mut dropped = None;
+        if self.capacity.is_some_and(|capacity| self.len >= capacity) {
+            dropped = match self.policy {
+                DropPolicy::HeadDrop => self.drop_head(),
+                _ => None,
+            };
+            if dropped.is_none() {
+                self.drops += 1;
+                return Some(packet);
+            }
+        }
+        self.push_queued(Queued {
+            packet,
+            enqueued: now,
+        });
+        dropped
+    }
+
+    fn push_queued(&mut self, queued: Queued) {
+        let key = self.mode.key(queued.packet.flow);
+        let queue = match self.queue_of.get(&key) {
+            Some(&queue) => queue,
+            None => {
+                let queue = self.free.pop().unwrap_or_else(|| {
+                    self.flows.queues.push(VecDeque::new());
+                    self.keys.push(key);
+                    self.flows.queues.len() - 1
+                });
+                self.keys[queue] = key;
+                self.queue_of.insert(key, queue);
+                self.flows.active.push_back(queue);
+                queue
+            }
+        };
+        self.flows.queues[queue].push_back(queued);
+        self.len += 1;
+    }
+
+    /// Drops the front packet of the longest flow
+    fn drop_head(&mut self) -> Option<Packet> {
+        let &queue = self
+            .flows
+            .active
+            .iter()
+            .max_by_key(|&&queue| self.flows.queues[queue].len())?;
+        let queued = self.flows.queues[queue]
+            .pop_front()
+            .expect("active queues aren't empty");
+        if self.flows.queues[queue].is_empty() {
+            self.flows.active.retain(|&active| active != queue);
+            self.release(queue);
+        }
+        self.len -= 1;
+        self.drops += 1;
+        Some(queued.packet)
+    }
+
+    fn release(&mut self, queue: usize) {
+        self.queue_of.remove(&self.keys[queue]);
+        self.free.push(queue);
+    }
+
+    pub(crate) fn head_length(&self) -> Option<usize> {
+        let &queue = self.flows.active.front()?;
+        self.flows.queues[queue]
+            .front()
+            .map(|queued| queued.packet.length())
+    }
+
+    fn pop_queued(&mut self) -> Option<Queued> {
+        let (queue, queued) = self.flows.take_from()?;
+        if self.flows.queues[queue].is_empty() {
+            self.release(queue);
+        }
+        self.len -= 1;
+        Some(queued)
+    }
+
+    /// The next packet to go out, after whatever CoDel drops on the way
+    pub(crate) fn pop(&mut self, now: Duration) -> Option<Packet> {
+        let DropPolicy::CoDel { target, interval } = self.policy else {
+            return self.pop_queued().map(|queued| queued.packet);
+        };
+        let mut dropped = 0;
+        let mut codel = std::mem::take(self.aqm.codel());
+        let packet = codel.dequeue(
+            now,
+            target,
+            interval,
+            || {
+                let queued = self.pop_queued()?;
+                let sojourn = now.saturating_sub(queued.enqueued);
+                Some((queued.packet, sojourn, self.len > 0))
+            },
+            |_| dropped += 1,
+        );
+        *self.aqm.codel() = codel;
+        self.drops += dropped;
+        packet
+    }
+
+    pub(crate) fn iter(&self) -> FlowsIter<'_> {
+        Flows {
+            queues: self
+                .flows
+                .queues
+                .iter()
+                .map(|queue| queue.iter().peekable())
+                .collect(),
+            active: self.flows.active.clone(),
+        }
+    }
+
+    pub(crate) fn iter_mut(&mut self) -> FlowsIterMut<'_> {
+        Flows {
+            queues: self
+                .flows
+                .queues
+                .iter_mut()
+                .map(|queue| queue.iter_mut().peekable())
+                .collect(),
+            active: self.flows.active.clone(),
+        }
+    }
+
+    /// Requeues everything under the new mode, in the order it would have
+    /// gone out
+    pub(crate) fn set_mode(&mut self, mode: FlowMode) {
+        if let FlowMode::Stochastic { buckets, .. } = mode {
+            assert!(buckets > 0, "need at least one bucket");
+        }
+        let queued: Vec<_> = std::iter::from_fn(|| self.pop_queued()).collect();
+        self.mode = mode;
+        self.flows.queues.clear();
+        self.queue_of.clear();
+        self.keys.clear();
+        self.free.clear();
+        for queued in queued {
+            self.push_queued(queued);
+        }
+    }
+
+    pub(crate) fn into_vec(mut self) -> Vec<Packet> {
+        std::iter::from_fn(|| self.pop_queued())
+            .map(|queued| queued.packet)
+            .collect()
+    }
+}
@@ -0,0 +1,61 @@
+use std::time::Duration;
+
+/// A cap on how fast a class sends
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    /// Bytes per second
+    pub rate: u64,
+    /// The most bytes that can go out at once after the class has been idle
+    pub burst: usize,
+}
+
+/// Tokens are bytes, a packet needs as many as it is long. One longer than
+/// the whole bucket can go once it's full, putting the bucket into debt.
+#[derive(Debug, Clone)]
+pub(crate) struct TokenBucket {
+    limit: RateLimit,
+    tokens: f64,
+    updated: Duration,
+}
+
+impl TokenBucket {
+    /// Starts out full
+    pub(crate) fn new(limit: RateLimit, now: Duration) -> Self {
+        Self {
+            limit,
+            tokens: limit.burst as f64,
+            updated: now,
+        }
+    }
+
+    pub(crate) fn refill(&mut self, now: Duration) {
+        let elapsed = now.saturating_sub(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.limit.rate as f64).min(self.limit.burst as f64);
+        self.updated = now;
+    }
+
+    fn needed(&self, length: usize) -> f64 {
+        length.min(self.limit.burst) as f64
+    }
+
+    pub(crate) fn allows(&self, length: usize) -> bool {
+        self.tokens >= self.needed(length)
+    }
+
+    pub(crate) fn spend(&mut self, length: usize) {
+        self.tokens -= length as f64;
+    }
+
+    /// When there will be enough tokens for `length`, None if there already
+    /// are or there never will be
+    pub(crate) fn eligible_at(&self, length: usize) -> Option<Duration> {
+        let missing = self.needed(length) - self.tokens;
+        if missing <= 0.0 || self.limit.rate == 0 {
+            return None;
+        }
+        // rounded up, so at least a nanosecond away, or a refill at that time
+        // could still come up a hair short
+        let nanos = (missing / self.limit.rate as f64 * 1e9).ceil();
+        Some(self.updated + Duration::from_nanos(nanos as u64))
+    }
+}
@@ -0,0 +1,281 @@
+//! Replaying packet traces through a [`Scheduler`] feeding a link of fixed speed
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::BufRead;
+use std::time::Duration;
+
+use crate::{Clock, ManualClock, Packet, Scheduler};
+
+/// A packet showing up in a trace
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Arrival {
+    /// Since the start of the trace
+    pub time: Duration,
+    pub class: u8,
+    pub flow: u16,
+    /// In bytes
+    pub size: usize,
+}
+
+impl Arrival {
+    /// The packet that arrives, carrying `payload`
+    pub fn packet(&self, payload: u64) -> Packet {
+        Packet::new(payload, self.class, self.flow).with_length(self.size)
+    }
+}
+
+/// Reads `time,class,flow,size` lines, with the time in seconds and the
+/// size in bytes. Blank lines and `#` comments are skipped, and so is the
+/// first line left if its time isn't a number, as a header. The arrivals
+/// come back in order of time.
+pub fn read_csv(reader: impl BufRead) -> Result<Vec<Arrival>, String> {
+    let mut arrivals = Vec::new();
+    let mut first = true;
+    for (index, line) in reader.lines().enumerate() {
+        let line = line.map_err(|error| error.to_string())?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let header = std::mem::replace(&mut first, false);
+        let number = index + 1;
+        let fields: Vec<_> = line.split(',').map(str::trim).collect();
+        let [time, class, flow, size] = fields[..] else {
+            return Err(format!(
+                "Line {number}: expected 4 fields, found {}",
+                fields.len()
+            ));
+        };
+        let Ok(time) = time.parse::<f64>() else {
+            if header {
+                continue;
+            }
+            return Err(format!("Line {number}: {time:?} is not a time"));
+        };
+        let invalid = |field: &str, what: &str| format!("Line {number}: {field:?} is not a {what}");
+        arrivals.push(Arrival {
+            time: Duration::try_from_secs_f64(time)
+                .map_err(|_| invalid(&time.to_string(), "time"))?,
+            class: class.parse().map_err(|_| invalid(class, "class"))?,
+            flow: flow.parse().map_err(|_| invalid(flow, "flow"))?,
+            size: size.parse().map_err(|_| invalid(size, "size"))?,
+        });
+    }
+    arrivals.sort_by_key(|arrival| arrival.time);
+    Ok(arrivals)
+}
+
+/// How a class or a flow fared
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Stats {
+    pub arrived: usize,
+    pub sent: usize,
+    /// Bytes sent
+    pub bytes: usize,
+    /// How long each packet sent waited before going out, shortest first
+    pub delays: Vec<Duration>,
+}
+
+impl Stats {
+    /// Packets that never went out, whether dropped or still queued at the end
+    pub fn dropped(&self) -> usize {
+        self.arrived - self.sent
+    }
+
+    /// Bytes per second
+    pub fn throughput(&self, over: Duration) -> f64 {
+        if over.is_zero() {
+            0.0
+        } else {
+            self.bytes as f64 / over.as_secs_f64()
+        }
+    }
+
+    /// The longest delay of the shortest `percentile` percent, None if
+    /// nothing was sent
+    pub fn delay_percentile(&self, percentile: f64) -> Option<Duration> {
+        if self.delays.is_empty() {
+            return None;
+        }
+        let rank = (percentile / 100.0 * self.delays.len() as f64).ceil() as usize;
+        self.delays
+            .get(rank.clamp(1, self.delays.len()) - 1)
+            .copied()
+    }
+
+    fn record(&mut self, size: usize, delay: Duration) {
+        self.sent += 1;
+        self.bytes += size;
+        self.delays.push(delay);
+    }
+}
+
+/// Jain's fairness index, 1 when every value is the same down to `1 / n`
+/// when one has it all
+pub fn jain_index(values: &[f64]) -> f64 {
+    let sum: f64 = values.iter().sum();
+    let squares: f64 = values.iter().map(|value| value * value).sum();
+    if squares == 0.0 {
+        1.0
+    } else {
+        sum * sum / (values.len() as f64 * squares)
+    }
+}
+
+/// What happened over a whole trace
+#[derive(Debug, Clone, PartialEq)]
+pub struct Report {
+    /// Until the last packet finished going out
+    pub duration: Duration,
+    pub weights: Vec<usize>,
+    pub classes: Vec<Stats>,
+    /// By class and flow
+    pub flows: BTreeMap<(u8, u16), Stats>,
+}
+
+impl Report {
+    /// Over the throughput of every flow
+    pub fn flow_fairness(&self) -> f64 {
+        let throughputs: Vec<_> = self
+            .flows
+            .values()
+            .map(|flow| flow.throughput(self.duration))
+            .collect();
+        jain_index(&throughputs)
+    }
+
+    /// Over the throughput of every class with traffic, divided by its weight
+    pub fn class_fairness(&self) -> f64 {
+        let throughputs: Vec<_> = self
+            .classes
+            .iter()
+            .zip(&self.weights)
+            .filter(|(class, weight)| class.arrived > 0 && **weight > 0)
+            .map(|(class, &weight)| class.throughput(self.duration) / weight as f64)
+            .collect();
+        jain_index(&throughputs)
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let row = |f: &mut fmt::Formatter<'_>, name: String, stats: &Stats| {
+            let delay = |percentile| match stats.delay_percentile(percentile) {
+                Some(delay) => format!("{:.3}", delay.as_secs_f64() * 1000.0),
+                None => "-".to_string(),
+            };
+            writeln!(
+                f,
+                "{name:>12} {:>8} {:>8} {:>8} {:>12.1} {:>9} {:>9} {:>9} {:>9}",
+                stats.arrived,
+                stats.sent,
+                stats.dropped(),
+                stats.throughput(self.duration),
+                delay(50.0),
+                delay(90.0),
+                delay(99.0),
+                delay(100.0),
+            )
+        };
+        let header = format!(
+            "{:>8} {:>8} {:>8} {:>12} {:>9} {:>9} {:>9} {:>9}",
+            "arrived", "sent", "dropped", "bytes/s", "p50 ms", "p90 ms", "p99 ms", "max ms"
+        );
+
+        writeln!(f, "duration {:.3}s", self.duration.as_secs_f64())?;
+        writeln!(f, "\n{:>12} {header}", "class:weight")?;
+        for (class, stats) in self.classes.iter().enumerate() {
+            if stats.arrived > 0 {
+                row(f, format!("{class}:{}", self.weights[class]), stats)?;
+            }
+        }
+        writeln!(f, "\n{:>12} {header}", "class/flow")?;
+        for (&(class, flow), stats) in &self.flows {
+            row(f, format!("{class}/{flow}"), stats)?;
+        }
+        writeln!(f, "\nJain's index over flows {:.4}", self.flow_fairness())?;
+        write!(
+            f,
+            "Jain's index over classes by weight {:.4}",
+            self.class_fairness()
+        )
+    }
+}
+
+/// Sends `arrivals` through `scheduler` onto a link of `link_rate` bytes per
+/// second, until nothing more can go out. The simulation drives the
+/// scheduler's clock, which has to start at 0. Fails if an arrival's class
+/// doesn't exist.
+pub fn simulate(
+    mut scheduler: Scheduler<ManualClock>,
+    arrivals: &[Arrival],
+    link_rate: u64,
+) -> Result<Report, String> {
+    assert!(link_rate > 0, "the link has to send something");
+    let mut classes = vec![Stats::default(); scheduler.class_count()];
+    let mut flows: BTreeMap<_, Stats> = BTreeMap::new();
+    let clock = scheduler.clock().clone();
+    // the next arrival still to come
+    let mut next = 0;
+    let mut link_free_at = Duration::ZERO;
+    loop {
+        let now = clock.now();
+        let start = next;
+        while arrivals
+            .get(next)
+            .is_some_and(|arrival| arrival.time <= now)
+        {
+            next += 1;
+        }
+        // the payload is where the packet is in the trace
+        let packets: Vec<_> = (start..next)
+            .map(|index| arrivals[index].packet(index as u64))
+            .collect();
+        scheduler.enqueue(&packets)?;
+        for arrival in &arrivals[start..next] {
+            classes[arrival.class as usize].arrived += 1;
+            flows
+                .entry((arrival.class, arrival.flow))
+                .or_default()
+                .arrived += 1;
+        }
+
+        while link_free_at <= now {
+            let Some(packet) = scheduler.dequeue() else {
+                break;
+            };
+            let arrival = arrivals[packet.payload as usize];
+            let delay = now - arrival.time;
+            classes[arrival.class as usize].record(arrival.size, delay);
+            flows
+                .get_mut(&(arrival.class, arrival.flow))
+                .expect("flows are recorded as they arrive")
+                .record(arrival.size, delay);
+            link_free_at = now + Duration::from_secs_f64(arrival.size as f64 / link_rate as f64);
+        }
+
+        let next_arrival = arrivals.get(next).map(|arrival| arrival.time);
+        let next_send = if link_free_at > now {
+            Some(link_free_at)
+        } else {
+            scheduler.next_eligible().filter(|&time| time > now)
+        };
+        match next_arrival.into_iter().chain(next_send).min() {
+            Some(time) => clock.set(time),
+            None => break,
+        }
+    }
+
+    for stats in classes.iter_mut().chain(flows.values_mut()) {
+        stats.delays.sort();
+    }
+    Ok(Report {
+        duration: link_free_at.max(clock.now()),
+        weights: (0..scheduler.class_count())
+            .map(|class| scheduler.weight(class as u8))
+            .collect(),
+        classes,
+        flows,
+    })
+}
@@ -1,27 +1,24 @@
 use super::*;
+use std::time::Duration;
 const WEIGHTS: [usize; 8] = [2, 5, 1, 8, 4, 3, 6, 7];
 
 #[test]
 #[should_panic]
 fn test_invalid_queue() {
-    let _: Packet = Packet::new(132, 42, 1).unwrap();
+    Scheduler::default()
+        .enqueue(&[Packet::new(132, 42, 1)])
+        .unwrap();
 }
 
 #[test]
 fn test_flow_order() {
     let mut scheduler = Scheduler::new(WEIGHTS);
-    let flow_1: Vec<_> = (0..10)
-        .map(|payload| Packet::new(payload, 0, 1).unwrap())
-        .collect();
-    let flow_2: Vec<_> = (0..4)
-        .map(|payload| Packet::new(payload, 7, 2).unwrap())
-        .collect();
-    let flow_3: Vec<_> = (0..8)
-        .map(|payload| Packet::new(payload, 0, 3).unwrap())
-        .collect();
-    scheduler.enqueue(&flow_1);
-    scheduler.enqueue(&flow_2);
-    scheduler.enqueue(&flow_3);
+    let flow_1: Vec<_> = (0..10).map(|payload| Packet::new(payload, 0, 1)).collect();
+    let flow_2: Vec<_> = (0..4).map(|payload| Packet::new(payload, 7, 2)).collect();
+    let flow_3: Vec<_> = (0..8).map(|payload| Packet::new(payload, 0, 3)).collect();
+    scheduler.enqueue(&flow_1).unwrap();
+    scheduler.enqueue(&flow_2).unwrap();
+    scheduler.enqueue(&flow_3).unwrap();
     assert_eq!(
         flow_1,
         scheduler
@@ -59,10 +56,8 @@ fn test_flow_order() {
 fn test_weighting() {
     let mut scheduler = Scheduler::new(WEIGHTS);
     for queue in 0..3 {
-        let packets: Vec<Packet> = (0..5)
-            .map(|flow| Packet::new(0, queue, flow).unwrap())
-            .collect();
-        scheduler.enqueue(&packets);
+        let packets: Vec<Packet> = (0..5).map(|flow| Packet::new(0, queue, flow)).collect();
+        scheduler.enqueue(&packets).unwrap();
     }
     for packet in scheduler.clone().iter().take(WEIGHTS[0]) {
         assert_eq!(0, packet.class);
@@ -83,37 +78,37 @@ fn test_weighting() {
 #[test]
 fn test_class_order() {
     let desired_order = [
-        Packet::new(0, 0, 0).unwrap(),
-        Packet::new(0, 1, 0).unwrap(),
-        Packet::new(0, 2, 0).unwrap(),
-        Packet::new(0, 3, 0).unwrap(),
-        Packet::new(0, 4, 0).unwrap(),
-        Packet::new(0, 5, 0).unwrap(),
-        Packet::new(0, 6, 0).unwrap(),
-        Packet::new(0, 7, 0).unwrap(),
+        Packet::new(0, 0, 0),
+        Packet::new(0, 1, 0),
+        Packet::new(0, 2, 0),
+        Packet::new(0, 3, 0),
+        Packet::new(0, 4, 0),
+        Packet::new(0, 5, 0),
+        Packet::new(0, 6, 0),
+        Packet::new(0, 7, 0),
     ];
     let input_order = [
-        Packet::new(0, 3, 0).unwrap(),
-        Packet::new(0, 0, 0).unwrap(),
-        Packet::new(0, 5, 0).unwrap(),
-        Packet::new(0, 2, 0).unwrap(),
-        Packet::new(0, 7, 0).unwrap(),
-        Packet::new(0, 4, 0).unwrap(),
-        Packet::new(0, 1, 0).unwrap(),
-        Packet::new(0, 6, 0).unwrap(),
+        Packet::new(0, 3, 0),
+        Packet::new(0, 0, 0),
+        Packet::new(0, 5, 0),
+        Packet::new(0, 2, 0),
+        Packet::new(0, 7, 0),
+        Packet::new(0, 4, 0),
+        Packet::new(0, 1, 0),
+        Packet::new(0, 6, 0),
     ];
     let scrambled_order = [
-        Packet::new(0, 7, 0).unwrap(),
-        Packet::new(0, 3, 0).unwrap(),
-        Packet::new(0, 2, 0).unwrap(),
-        Packet::new(0, 0, 0).unwrap(),
-        Packet::new(0, 4, 0).unwrap(),
-        Packet::new(0, 1, 0).unwrap(),
-        Packet::new(0, 6, 0).unwrap(),
-        Packet::new(0, 5, 0).unwrap(),
+        Packet::new(0, 7, 0),
+        Packet::new(0, 3, 0),
+        Packet::new(0, 2, 0),
+        Packet::new(0, 0, 0),
+        Packet::new(0, 4, 0),
+        Packet::new(0, 1, 0),
+        Packet::new(0, 6, 0),
+        Packet::new(0, 5, 0),
     ];
     let mut scheduler = Scheduler::default();
-    scheduler.enqueue(&input_order);
+    scheduler.enqueue(&input_order).unwrap();
     let scheduled_packets: Vec<_> = scheduler.iter().collect();
     assert_eq!(desired_order.iter().collect::<Vec<_>>(), scheduled_packets);
     assert_ne!(
@@ -121,3 +116,827 @@ fn test_class_order() {
         scheduled_packets
     );
 }
+
+/// Bytes each class has been served after every packet
+fn bytes_served(packets: impl Iterator<Item = Packet>) -> Vec<[usize; 8]> {
+    packets
+        .scan([0; 8], |served, packet| {
+            served[packet.class as usize] += packet.length();
+            Some(*served)
+        })
+        .collect()
+}
+
+#[test]
+fn test_deficit_byte_fairness() {
+    const QUANTUM: usize = 500;
+    let mut wrr = Scheduler::default();
+    let mut drr = Scheduler::with_mode([1; 8], Mode::Deficit { quantum: QUANTUM });
+    let jumbo: Vec<_> = (0..100)
+        .map(|payload| Packet::new(payload, 0, 0).with_length(1500))
+        .collect();
+    let small: Vec<_> = (0..1000)
+        .map(|payload| Packet::new(payload, 1, 1).with_length(64))
+        .collect();
+    for scheduler in [&mut wrr, &mut drr] {
+        scheduler.enqueue(&jumbo).unwrap();
+        scheduler.enqueue(&small).unwrap();
+    }
+
+    // while both classes are backlogged neither gets ahead by more than a
+    // quantum plus a packet
+    let served = bytes_served(drr.clone().into_iter());
+    let both_backlogged = served
+        .iter()
+        .take_while(|served| served[0] < 100 * 1500 && served[1] < 1000 * 64);
+    for served in both_backlogged {
+        assert!(served[0].abs_diff(served[1]) <= QUANTUM + 1500);
+    }
+    assert_eq!(served.last().unwrap()[..2], [100 * 1500, 1000 * 64]);
+
+    // counting packets the jumbo class takes nearly all the bandwidth
+    let served = bytes_served(wrr.into_iter());
+    assert_eq!(served[199][..2], [100 * 1500, 100 * 64]);
+
+    let order: Vec<_> = drr.clone().into_iter().collect();
+    assert!(drr.iter().eq(&order));
+    assert!(drr.iter_mut().map(|packet| *packet).eq(order));
+}
+
+#[test]
+fn test_deficit_weights_and_large_packets() {
+    let mut scheduler =
+        Scheduler::with_mode([1, 3, 0, 0, 0, 0, 0, 1], Mode::Deficit { quantum: 100 });
+    let packets: Vec<_> = (0..30)
+        .map(|payload| Packet::new(payload, 0, 0).with_length(100))
+        .chain((0..30).map(|payload| Packet::new(payload, 1, 0).with_length(100)))
+        // bigger than anything the class earns in one round
+        .chain((0..2).map(|payload| Packet::new(payload, 7, 0).with_length(450)))
+        .collect();
+    scheduler.enqueue(&packets).unwrap();
+
+    let served = bytes_served(scheduler.iter().copied());
+    // class 1 earns three times the bytes of class 0 every round
+    assert_eq!(served[15][..2], [400, 1200]);
+    assert_eq!(served.len(), 62);
+    let order: Vec<_> = scheduler.iter().map(|packet| packet.class).collect();
+    let first_jumbo = order.iter().position(|&class| class == 7).unwrap();
+    // class 7 has to save up for five rounds before it can send
+    assert_eq!(order[..first_jumbo].iter().filter(|&&c| c == 0).count(), 5);
+}
+
+#[test]
+fn test_deficit_zero_length() {
+    let mut scheduler = Scheduler::with_mode([1; 2], Mode::Deficit { quantum: 100 });
+    let packets: Vec<_> = (0..1000)
+        .map(|payload| Packet::new(payload, 0, 0))
+        .chain([Packet::new(0, 1, 0).with_length(50)])
+        .collect();
+    scheduler.enqueue(&packets).unwrap();
+
+    // packets without a length cost a byte rather than nothing, so they
+    // can't hog the link
+    let order: Vec<_> = scheduler.iter().map(|packet| packet.class).collect();
+    assert_eq!(order.iter().position(|&class| class == 1), Some(100));
+    assert_eq!(
+        scheduler.into_iter().position(|packet| packet.class == 1),
+        Some(100)
+    );
+}
+
+#[test]
+fn test_strict_priority() {
+    let mut scheduler = Scheduler::new(WEIGHTS);
+    scheduler.set_class_mode(
+        7,
+        ClassMode::Strict {
+            starvation_guard: None,
+        },
+    );
+    scheduler.set_class_mode(
+        6,
+        ClassMode::Strict {
+            starvation_guard: None,
+        },
+    );
+    for class in 0..8 {
+        let packets: Vec<_> = (0..10)
+            .map(|payload| Packet::new(payload, class, 0))
+            .collect();
+        scheduler.enqueue(&packets).unwrap();
+    }
+    let classes: Vec<_> = scheduler.iter().map(|packet| packet.class).collect();
+    assert_eq!(classes[..10], [7; 10]);
+    assert_eq!(classes[10..20], [6; 10]);
+    // the rest share by weight as before
+    assert_eq!(classes[20..22], [0; 2]);
+    assert_eq!(classes[22..27], [1; 5]);
+    assert_eq!(classes.len(), 80);
+}
+
+#[test]
+fn test_starvation_guard() {
+    let mut scheduler = Scheduler::new(WEIGHTS);
+    scheduler.set_class_mode(
+        7,
+        ClassMode::Strict {
+            starvation_guard: Some(3),
+        },
+    );
+    let packets: Vec<_> = (0..9)
+        .map(|payload| Packet::new(payload, 7, 0))
+        .chain((0..4).map(|payload| Packet::new(payload, 0, 0)))
+        .collect();
+    scheduler.enqueue(&packets).unwrap();
+    let classes: Vec<_> = scheduler.iter().map(|packet| packet.class).collect();
+    assert_eq!(classes, [7, 7, 7, 0, 7, 7, 7, 0, 7, 7, 7, 0, 0]);
+    assert!(
+        scheduler
+            .clone()
+            .into_iter()
+            .map(|packet| packet.class)
+            .eq(classes)
+    );
+}
+
+#[test]
+fn test_class_count() {
+    let mut scheduler = Scheduler::new([1, 2]);
+    let packets = [Packet::new(0, 0, 0), Packet::new(0, 2, 0)];
+    assert!(scheduler.enqueue(&packets).is_err());
+    // nothing is enqueued when one packet is rejected
+    assert_eq!(scheduler.iter().count(), 0);
+
+    assert_eq!(scheduler.add_class(3), Ok(2));
+    scheduler.enqueue(&packets).unwrap();
+    scheduler.enqueue(&packets[1..]).unwrap();
+    let classes: Vec<_> = scheduler.iter().map(|packet| packet.class).collect();
+    assert_eq!(classes, [0, 2, 2]);
+
+    assert_eq!(scheduler.remove_class(), Some(vec![packets[1]; 2]));
+    assert_eq!(scheduler.class_count(), 2);
+    assert!(scheduler.enqueue(&packets[1..]).is_err());
+    assert_eq!(scheduler.iter().count(), 1);
+
+    scheduler.remove_class();
+    scheduler.remove_class();
+    assert_eq!(scheduler.remove_class(), None);
+    assert_eq!(scheduler.iter().count(), 0);
+
+    let mut scheduler = Scheduler::new([]);
+    for class in 0..MAX_CLASSES {
+        assert_eq!(scheduler.add_class(1), Ok(class as u8));
+    }
+    assert!(scheduler.add_class(1).is_err());
+}
+
+/// The flow of every packet, and whether each flow came out in order
+fn flows_in_order<'a>(packets: impl Iterator<Item = &'a Packet>) -> (Vec<u16>, bool) {
+    let mut next_payload = std::collections::HashMap::new();
+    let mut in_order = true;
+    let flows = packets
+        .map(|packet| {
+            let next = next_payload.entry(packet.flow).or_insert(0);
+            in_order &= packet.payload == *next;
+            *next += 1;
+            packet.flow
+        })
+        .collect();
+    (flows, in_order)
+}
+
+#[test]
+fn test_flow_round_robin() {
+    let mut scheduler = Scheduler::default();
+    scheduler.set_flow_mode(0, FlowMode::RoundRobin);
+    let aggressive: Vec<_> = (0..20).map(|payload| Packet::new(payload, 0, 1)).collect();
+    let quiet: Vec<_> = (0..3).map(|payload| Packet::new(payload, 0, 2)).collect();
+    scheduler.enqueue(&aggressive).unwrap();
+    scheduler.enqueue(&quiet).unwrap();
+
+    let (flows, in_order) = flows_in_order(scheduler.iter());
+    assert!(in_order);
+    assert_eq!(flows[..7], [1, 2, 1, 2, 1, 2, 1]);
+    assert_eq!(flows.len(), 23);
+
+    // the same order whichever way the packets are taken out
+    let packets: Vec<_> = scheduler.iter().copied().collect();
+    assert!(
+        scheduler
+            .iter_mut()
+            .map(|packet| *packet)
+            .eq(packets.clone())
+    );
+    assert!(scheduler.clone().into_iter().eq(packets));
+
+    // a flow that empties out starts at the back of the line again
+    let mut scheduler = scheduler.into_iter();
+    assert_eq!(scheduler.nth(5).map(|packet| packet.flow), Some(2));
+    scheduler.scheduler.enqueue(&quiet).unwrap();
+    let flows: Vec<_> = scheduler.take(4).map(|packet| packet.flow).collect();
+    assert_eq!(flows, [1, 2, 1, 2]);
+}
+
+#[test]
+fn test_stochastic_fair_queuing() {
+    let mut scheduler = Scheduler::default();
+    let packets: Vec<_> = (0..200)
+        .map(|payload| Packet::new(payload / 20, 3, (payload % 20) as u16))
+        .collect();
+    scheduler.enqueue(&packets).unwrap();
+    for perturbation in 0..4 {
+        scheduler.set_flow_mode(
+            3,
+            FlowMode::Stochastic {
+                buckets: 8,
+                perturbation,
+            },
+        );
+        let (flows, in_order) = flows_in_order(scheduler.iter());
+        assert!(in_order);
+        assert_eq!(flows.len(), 200);
+        // every bucket in use gets a turn before any gets a second one
+        let first_round: std::collections::HashSet<_> = flows[..8].iter().collect();
+        assert!(first_round.len() > 1);
+    }
+
+    // switching back keeps whatever order the packets were going out in
+    let order: Vec<_> = scheduler.iter().copied().collect();
+    scheduler.set_flow_mode(3, FlowMode::Fifo);
+    assert!(scheduler.iter().eq(&order));
+}
+
+#[test]
+fn test_tail_and_head_drop() {
+    let mut scheduler = Scheduler::default();
+    scheduler.set_capacity(0, Some(3));
+    scheduler.set_capacity(1, Some(3));
+    scheduler.set_drop_policy(1, DropPolicy::HeadDrop);
+    let packets: Vec<_> = (0..5)
+        .map(|payload| Packet::new(payload, 0, 0))
+        .chain((0..5).map(|payload| Packet::new(payload, 1, 0)))
+        .collect();
+    let dropped = scheduler.enqueue(&packets).unwrap();
+    assert_eq!(dropped, [&packets[3..5], &packets[5..7]].concat());
+    assert_eq!((scheduler.drops(0), scheduler.drops(1)), (2, 2));
+    assert_eq!((scheduler.queued(0), scheduler.queued(1)), (3, 3));
+    let kept: Vec<_> = scheduler.iter().map(|packet| packet.payload).collect();
+    assert_eq!(kept, [0, 2, 1, 3, 2, 4]);
+
+    // head drop takes from the longest flow
+    scheduler.set_flow_mode(1, FlowMode::RoundRobin);
+    let heavy = Packet::new(9, 1, 1);
+    assert_eq!(scheduler.enqueue(&[heavy]).unwrap(), [packets[7]]);
+    assert_eq!(scheduler.enqueue(&[heavy]).unwrap(), [packets[8]]);
+    assert_eq!(scheduler.enqueue(&[heavy]).unwrap(), [heavy]);
+    assert_eq!(scheduler.drops(1), 5);
+}
+
+#[test]
+fn test_random_early_detection() {
+    let mut scheduler = Scheduler::default();
+    scheduler.set_drop_policy(
+        2,
+        DropPolicy::Red {
+            min_threshold: 5,
+            max_threshold: 15,
+            max_probability: 0.5,
+            weight: 1.0,
+        },
+    );
+    let packets: Vec<_> = (0..100).map(|payload| Packet::new(payload, 2, 0)).collect();
+    let dropped = scheduler.enqueue(&packets).unwrap();
+    // nothing is dropped below the minimum, everything past the maximum
+    assert!(dropped.iter().all(|packet| packet.payload > 5));
+    assert_eq!(scheduler.queued(2), 15);
+    assert_eq!(dropped.len(), 85);
+    assert_eq!(scheduler.drops(2), 85);
+    // in between it's down to chance
+    assert!(dropped[0].payload < 15);
+}
+
+#[test]
+fn test_codel() {
+    let clock = ManualClock::default();
+    let mut scheduler = Scheduler::with_clock([1], Mode::default(), clock.clone());
+    scheduler.set_drop_policy(
+        0,
+        DropPolicy::CoDel {
+            target: Duration::from_millis(5),
+            interval: Duration::from_millis(100),
+        },
+    );
+    let packets: Vec<_> = (0..100).map(|payload| Packet::new(payload, 0, 0)).collect();
+    scheduler.enqueue(&packets).unwrap();
+
+    // a queue that drains straight away never builds up any delay
+    assert!(scheduler.clone().into_iter().eq(packets.clone()));
+
+    // one that drains slowly gets dropped from more and more often
+    let mut packets_out = scheduler.into_iter();
+    let mut sent = Vec::new();
+    for packet in packets_out.by_ref() {
+        sent.push(packet.payload);
+        clock.advance(Duration::from_millis(10));
+    }
+    let drops = packets_out.scheduler.drops(0);
+    assert_eq!(sent.len() + drops, 100);
+    // nothing goes until the delay has been over target for an interval
+    assert_eq!(sent[..12], [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 12]);
+    let dropped: Vec<_> = (0..100).filter(|payload| !sent.contains(payload)).collect();
+    assert_eq!(dropped.len(), drops);
+    let spacing: Vec<_> = dropped.windows(2).map(|pair| pair[1] - pair[0]).collect();
+    assert!(spacing.len() > 5);
+    assert!(spacing.first() > spacing.last());
+}
+
+#[test]
+fn test_dequeue_keeps_its_place() {
+    let packets: Vec<Vec<_>> = (0..4)
+        .map(|class| {
+            (0..30)
+                .map(|payload| Packet::new(payload, class, 0))
+                .collect()
+        })
+        .collect();
+    let mut all_at_once = Scheduler::new(WEIGHTS);
+    for class in &packets {
+        all_at_once.enqueue(class).unwrap();
+    }
+    let expected: Vec<_> = all_at_once.into_iter().collect();
+
+    // as long as no class runs dry, arrivals don't change the order
+    let mut scheduler = Scheduler::new(WEIGHTS);
+    let mut sent = Vec::new();
+    for (chunk, batch) in [(0..10, 5), (10..20, 7), (20..30, 0)] {
+        for class in &packets {
+            scheduler.enqueue(&class[chunk.clone()]).unwrap();
+        }
+        sent.extend(std::iter::from_fn(|| scheduler.dequeue()).take(batch));
+        // iterating shows what comes next without taking it
+        assert_eq!(scheduler.iter().next(), expected.get(sent.len()));
+    }
+    sent.extend(scheduler.dequeue_batch(usize::MAX));
+    assert_eq!(sent, expected);
+    assert_eq!(scheduler.dequeue(), None);
+
+    // a class that ran dry picks up where the round is at
+    scheduler.enqueue(&packets[3][..10]).unwrap();
+    scheduler.enqueue(&packets[0][..10]).unwrap();
+    let classes: Vec<_> = scheduler
+        .dequeue_batch(12)
+        .iter()
+        .map(|packet| packet.class)
+        .collect();
+    assert_eq!(classes, [3, 3, 3, 3, 3, 3, 3, 3, 0, 0, 3, 3]);
+}
+
+#[test]
+fn test_rate_limit() {
+    let clock = ManualClock::default();
+    let mut scheduler = Scheduler::with_clock([1, 1], Mode::default(), clock.clone());
+    scheduler.set_rate(
+        0,
+        Some(RateLimit {
+            rate: 1000,
+            burst: 1500,
+        }),
+    );
+    let packets: Vec<_> = (0..10)
+        .map(|payload| Packet::new(payload, 0, 0).with_length(500))
+        .chain((0..10).map(|payload| Packet::new(payload, 1, 0).with_length(500)))
+        .collect();
+    scheduler.enqueue(&packets).unwrap();
+
+    // the burst goes out straight away, the unlimited class takes the rest
+    let sent = scheduler.dequeue_batch(usize::MAX);
+    assert_eq!(sent.iter().filter(|packet| packet.class == 0).count(), 3);
+    assert_eq!(sent.len(), 13);
+    assert_eq!(scheduler.next_eligible(), Some(Duration::from_millis(500)));
+
+    // after that one packet every half second, however idle the link is
+    clock.set(Duration::from_millis(499));
+    assert_eq!(scheduler.dequeue(), None);
+    for &packet in &packets[3..10] {
+        clock.set(scheduler.next_eligible().unwrap());
+        assert_eq!(scheduler.dequeue_batch(usize::MAX), [packet]);
+    }
+    assert_eq!(clock.now(), Duration::from_millis(3500));
+    assert_eq!(scheduler.next_eligible(), None);
+
+    // a packet bigger than the burst waits for a full bucket
+    scheduler
+        .enqueue(&[Packet::new(0, 0, 0).with_length(4000)])
+        .unwrap();
+    assert_eq!(scheduler.dequeue(), None);
+    assert_eq!(scheduler.next_eligible(), Some(Duration::from_millis(5000)));
+    clock.set(Duration::from_millis(5000));
+    assert!(scheduler.dequeue().is_some());
+    assert_eq!(scheduler.next_eligible(), None);
+}
+
+#[test]
+fn test_rate_limit_boundary() {
+    let clock = ManualClock::default();
+    let mut scheduler = Scheduler::with_clock([1], Mode::default(), clock.clone());
+    // a third of a byte every second doesn't come out even in nanoseconds
+    scheduler.set_rate(0, Some(RateLimit { rate: 3, burst: 1 }));
+    let packets: Vec<_> = (0..10)
+        .map(|payload| Packet::new(payload, 0, 0).with_length(1))
+        .collect();
+    scheduler.enqueue(&packets).unwrap();
+    // nothing is held back while the class can send
+    assert_eq!(scheduler.next_eligible(), None);
+
+    let mut sent = 0;
+    loop {
+        sent += scheduler.dequeue_batch(usize::MAX).len();
+        let Some(next) = scheduler.next_eligible() else {
+            break;
+        };
+        assert!(next > clock.now());
+        clock.set(next);
+    }
+    assert_eq!(sent, 10);
+    assert!(clock.now() >= Duration::from_secs(3));
+    assert!(clock.now() < Duration::from_millis(3001));
+}
+
+#[test]
+fn test_tree_shares() {
+    // tenant A gets 60%, split 3:1 between its voice and bulk traffic
+    let mut tree = TreeScheduler::new();
+    let root = tree.root();
+    let tenant_a = tree.add_inner(root, 3).unwrap();
+    let tenant_b = tree.add_leaf(root, 2).unwrap();
+    let voice = tree.add_leaf(tenant_a, 3).unwrap();
+    let bulk = tree.add_leaf(tenant_a, 1).unwrap();
+    assert!(tree.add_leaf(voice, 1).is_err());
+    for (flow, leaf) in [voice, bulk, tenant_b].into_iter().enumerate() {
+        let packets: Vec<_> = (0..100)
+            .map(|payload| Packet::new(payload, 0, flow as u16))
+            .collect();
+        tree.enqueue(leaf, &packets).unwrap();
+    }
+    assert_eq!(tree.queued(root), 300);
+    assert_eq!(tree.queued(tenant_a), 200);
+
+    let mut sent = [0; 3];
+    for packet in tree.dequeue_batch(100) {
+        sent[packet.flow as usize] += 1;
+    }
+    assert_eq!(sent, [45, 15, 40]);
+
+    // strict priority within a tenant only jumps ahead of its siblings
+    tree.set_class_mode(
+        voice,
+        ClassMode::Strict {
+            starvation_guard: None,
+        },
+    );
+    let mut sent = [0; 3];
+    for packet in tree.dequeue_batch(50) {
+        sent[packet.flow as usize] += 1;
+    }
+    assert_eq!(sent, [30, 0, 20]);
+
+    // an idle sibling's share goes to the rest
+    let rest = tree.dequeue_batch(usize::MAX);
+    assert_eq!(rest.len(), 150);
+    assert!(
+        rest[rest.len() - 50..]
+            .iter()
+            .all(|packet| packet.flow == 1)
+    );
+    assert_eq!(tree.dequeue(), None);
+}
+
+#[test]
+fn test_tree_borrowing() {
+    let clock = ManualClock::default();
+    let mut tree = TreeScheduler::with_clock(clock.clone());
+    let root = tree.root();
+    let limit = |rate| {
+        Some(RateLimit {
+            rate,
+            burst: rate as usize / 10,
+        })
+    };
+    // a 2000 B/s link guaranteeing each side half
+    tree.set_rate(root, limit(2000));
+    let greedy = tree.add_leaf(root, 1).unwrap();
+    let modest = tree.add_leaf(root, 1).unwrap();
+    tree.set_rate(greedy, limit(1000));
+    tree.set_ceil(greedy, limit(2000));
+    tree.set_rate(modest, limit(1000));
+
+    let send_for = |tree: &mut TreeScheduler<ManualClock>, seconds| {
+        let mut sent = [0; 2];
+        let end = clock.now() + Duration::from_secs(seconds);
+        while clock.now() < end {
+            for packet in tree.dequeue_batch(usize::MAX) {
+                sent[packet.flow as usize] += packet.length();
+            }
+            clock.set(tree.next_eligible().unwrap_or(end).min(end));
+        }
+        sent
+    };
+    let packets = |flow| -> Vec<_> {
+        (0..1000)
+            .map(|payload| Packet::new(payload, 0, flow).with_length(100))
+            .collect()
+    };
+
+    // alone the greedy side borrows the whole link
+    tree.enqueue(greedy, &packets(0)).unwrap();
+    let [greedy_bytes, _] = send_for(&mut tree, 10);
+    assert!((20_000..=20_500).contains(&greedy_bytes));
+
+    // and only gets its own half back once the other side is busy
+    tree.enqueue(modest, &packets(1)).unwrap();
+    let [greedy_bytes, modest_bytes] = send_for(&mut tree, 10);
+    assert!((9_500..=10_500).contains(&greedy_bytes));
+    assert!((9_500..=10_500).contains(&modest_bytes));
+
+    // with the greedy side shut off, the modest side still can't borrow
+    tree.set_rate(greedy, None);
+    tree.set_ceil(greedy, Some(RateLimit { rate: 0, burst: 0 }));
+    let [_, modest_bytes] = send_for(&mut tree, 10);
+    assert!((9_500..=10_500).contains(&modest_bytes));
+}
+
+#[test]
+fn test_read_csv() {
+    let trace = "time,class,flow,size\n\n# a comment\n0.5, 1, 7, 1500\n0.25,0,3,64\n";
+    let arrivals = sim::read_csv(trace.as_bytes()).unwrap();
+    assert_eq!(
+        arrivals,
+        vec![
+            sim::Arrival {
+                time: Duration::from_millis(250),
+                class: 0,
+                flow: 3,
+                size: 64,
+            },
+            sim::Arrival {
+                time: Duration::from_millis(500),
+                class: 1,
+                flow: 7,
+                size: 1500,
+            },
+        ]
+    );
+
+    assert!(sim::read_csv("0,0,0\n".as_bytes()).is_err());
+    assert!(sim::read_csv("0,0,0,64\n0,256,0,64\n".as_bytes()).is_err());
+    assert!(sim::read_csv("0,0,0,64\nlater,0,0,64\n".as_bytes()).is_err());
+    // only the first line can be a header
+    assert!(sim::read_csv("time,class,flow,size\nt,c,f,s\n0,0,0,64\n".as_bytes()).is_err());
+}
+
+#[test]
+fn test_jain_index() {
+    assert_eq!(sim::jain_index(&[2.0, 2.0, 2.0]), 1.0);
+    assert_eq!(sim::jain_index(&[1.0, 0.0]), 0.5);
+    assert_eq!(sim::jain_index(&[]), 1.0);
+}
+
+#[test]
+fn test_simulate() {
+    let arrivals: Vec<_> = (0..20)
+        .map(|index| sim::Arrival {
+            time: Duration::ZERO,
+            class: index % 2,
+            flow: index as u16 % 4,
+            size: 100,
+        })
+        .collect();
+    let mut scheduler = Scheduler::with_clock([3, 1], Mode::PacketCount, ManualClock::default());
+    scheduler.set_capacity(1, Some(6));
+
+    // 100 bytes a packet over 1000 bytes a second
+    let report = sim::simulate(scheduler, &arrivals, 1000).unwrap();
+    assert_eq!(report.duration, Duration::from_millis(1600));
+    assert_eq!(report.classes[0].sent, 10);
+    assert_eq!(report.classes[1].dropped(), 4);
+    assert_eq!(report.flows.len(), 4);
+    assert_eq!(report.flows[&(1, 3)].sent, 3);
+
+    let class = &report.classes[0];
+    assert_eq!(class.delay_percentile(0.0), Some(Duration::ZERO));
+    assert_eq!(
+        class.delay_percentile(100.0),
+        Some(Duration::from_millis(1200))
+    );
+    assert_eq!(class.throughput(report.duration), 625.0);
+    assert!(report.flow_fairness() < 1.0);
+}
+
+#[test]
+fn test_simulate_all_dropped() {
+    let arrivals = [sim::Arrival {
+        time: Duration::ZERO,
+        class: 0,
+        flow: 0,
+        size: 100,
+    }];
+    let mut scheduler = Scheduler::with_clock([1], Mode::PacketCount, ManualClock::default());
+    scheduler.set_capacity(0, Some(0));
+
+    let report = sim::simulate(scheduler, &arrivals, 1000).unwrap();
+    let flow = &report.flows[&(0, 0)];
+    assert_eq!(flow.dropped(), 1);
+    assert_eq!(flow.delay_percentile(50.0), None);
+    assert_eq!(report.classes[0].delay_percentile(99.0), None);
+    assert!(report.to_string().contains('-'));
+}
+
+/// An Ethernet frame of a UDP packet over IPv4
+fn udp_frame(dscp: u8, source_port: u16) -> Vec<u8> {
+    let mut frame = vec![0; 12];
+    frame.extend([0x08, 0x00]);
+    frame.extend([0x45, dscp << 2, 0, 28, 0, 0, 0, 0, 64, 17, 0, 0]);
+    frame.extend([10, 0, 0, 1, 10, 0, 0, 2]);
+    frame.extend(source_port.to_be_bytes());
+    frame.extend([0, 53, 0, 8, 0, 0]);
+    frame
+}
+
+/// A VLAN tagged Ethernet frame of a TCP packet over IPv6
+fn tcp6_frame(dscp: u8) -> Vec<u8> {
+    let mut frame = vec![0; 12];
+    frame.extend([0x81, 0x00, 0, 7, 0x86, 0xdd]);
+    frame.extend([0x60 | dscp >> 2, (dscp & 3) << 6, 0, 0, 0, 20, 6, 64]);
+    frame.extend([0xfe; 32]);
+    frame.extend([0x1f, 0x90, 0x01, 0xbb]);
+    frame.extend([0; 16]);
+    frame
+}
+
+#[test]
+fn test_read_pcap() {
+    // little endian, in microseconds, over Ethernet
+    let mut capture = vec![0xd4, 0xc3, 0xb2, 0xa1, 2, 0, 4, 0];
+    capture.extend([0; 8]);
+    capture.extend(65535u32.to_le_bytes());
+    capture.extend(1u32.to_le_bytes());
+    let arp = [[0; 12].as_slice(), &[0x08, 0x06], &[0; 28]].concat();
+    let frames = [
+        (10, 750_000, udp_frame(46, 1000), 1500),
+        (10, 500_000, udp_frame(46, 1000), 42),
+        (11, 0, arp, 42),
+        (11, 0, tcp6_frame(8), 74),
+        (12, 0, udp_frame(0, 2000), 42),
+    ];
+    for (seconds, micros, frame, length) in frames {
+        for field in [seconds, micros, frame.len() as u32, length] {
+            capture.extend(field.to_le_bytes());
+        }
+        capture.extend(frame);
+    }
+
+    let arrivals = pcap::read_pcap(&capture).unwrap();
+    let times: Vec<_> = arrivals.iter().map(|arrival| arrival.time).collect();
+    assert_eq!(times, [0, 250, 500, 1500].map(Duration::from_millis));
+    let classes: Vec<_> = arrivals.iter().map(|arrival| arrival.class).collect();
+    assert_eq!(classes, [5, 5, 1, 0]);
+    // the whole frame on the wire, even when less was captured
+    assert_eq!(arrivals[1].size, 1500);
+    assert_eq!(arrivals[0].flow, arrivals[1].flow);
+    assert_ne!(arrivals[0].flow, arrivals[3].flow);
+    assert_eq!(
+        arrivals[1].packet(7),
+        Packet::new(7, 5, arrivals[1].flow).with_length(1500)
+    );
+
+    capture.truncate(capture.len() - 1);
+    assert!(pcap::read_pcap(&capture).is_err());
+    assert!(pcap::read_pcap(b"time,class,flow,size").is_err());
+}
+
+#[test]
+fn test_read_pcapng() {
+    let block = |kind: u32, body: &[u8]| {
+        let length = (12 + body.len()) as u32;
+        [
+            kind.to_be_bytes().as_slice(),
+            &length.to_be_bytes(),
+            body,
+            &length.to_be_bytes(),
+        ]
+        .concat()
+    };
+    let packet = |ticks: u64, frame: Vec<u8>| {
+        let mut body = 0u32.to_be_bytes().to_vec();
+        body.extend(((ticks >> 32) as u32).to_be_bytes());
+        body.extend((ticks as u32).to_be_bytes());
+        body.extend((frame.len() as u32).to_be_bytes());
+        body.extend((frame.len() as u32).to_be_bytes());
+        body.resize(body.len() + frame.len().next_multiple_of(4), 0);
+        body[20..20 + frame.len()].copy_from_slice(&frame);
+        block(6, &body)
+    };
+
+    // big endian, with an interface in nanoseconds
+    let mut capture = block(
+        0x0a0d_0d0a,
+        &[
+            0x1a, 0x2b, 0x3c, 0x4d, 0, 1, 0, 0, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+        ],
+    );
+    capture.extend(block(
+        1,
+        &[
+            0, 1, 0, 0, 0, 0, 0xff, 0xff, 0, 9, 0, 1, 9, 0, 0, 0, 0, 0, 0, 0,
+        ],
+    ));
+    capture.extend(packet(5_000_000_000, udp_frame(10, 1000)));
+    capture.extend(block(2, &[0; 8]));
+    capture.extend(packet(5_000_001_500, tcp6_frame(46)));
+
+    let arrivals = pcap::read_pcap(&capture).unwrap();
+    assert_eq!(arrivals.len(), 2);
+    assert_eq!(arrivals[1].time, Duration::from_nanos(1500));
+    assert_eq!((arrivals[0].class, arrivals[1].class), (1, 5));
+    assert_eq!(arrivals[1].size, tcp6_frame(46).len());
+}
+
+#[test]
+fn test_deficit_with_rate_limit() {
+    let clock = ManualClock::default();
+    let mut scheduler =
+        Scheduler::with_clock([1, 1], Mode::Deficit { quantum: 100 }, clock.clone());
+    scheduler.set_rate(
+        0,
+        Some(RateLimit {
+            rate: 500,
+            burst: 150,
+        }),
+    );
+    let packets: Vec<_> = (0..200)
+        .map(|payload| Packet::new(payload, payload as u8 % 2, 0).with_length(150))
+        .collect();
+    scheduler.enqueue(&packets).unwrap();
+
+    // a link of 1000 bytes a second, so the limit lets class 0 have its
+    // fair half, as long as it keeps what it saved up while held back
+    let mut sent = [0; 2];
+    for _ in 0..60 {
+        let packet = scheduler.dequeue().unwrap();
+        sent[packet.class as usize] += packet.length();
+        clock.set(clock.now() + Duration::from_millis(150));
+    }
+    assert_eq!(sent, [4500, 4500]);
+}
+
+#[test]
+fn test_tree_rate_under_borrowing_parent() {
+    let clock = ManualClock::default();
+    let mut tree = TreeScheduler::with_clock(clock.clone());
+    let root = tree.root();
+    let parent = tree.add_inner(root, 1).unwrap();
+    let within = tree.add_leaf(parent, 1).unwrap();
+    let borrowing = tree.add_leaf(parent, 1).unwrap();
+    let uncle = tree.add_leaf(root, 1).unwrap();
+    // the clock never moves, so the buckets never refill
+    let limit = |burst| Some(RateLimit { rate: 1, burst });
+    for node in [parent, borrowing, uncle] {
+        tree.set_rate(node, limit(0));
+        tree.set_ceil(node, limit(1_000_000));
+    }
+    tree.set_rate(within, limit(1000));
+    tree.set_ceil(within, limit(1_000_000));
+    let packets = |flow| -> Vec<_> {
+        (0..5)
+            .map(|payload| Packet::new(payload, 0, flow).with_length(100))
+            .collect()
+    };
+
+    // put the parent, its other child and its sibling over their rates
+    tree.enqueue(borrowing, &packets(1)[..1]).unwrap();
+    tree.enqueue(uncle, &packets(2)[..1]).unwrap();
+    assert_eq!(tree.dequeue_batch(usize::MAX).len(), 2);
+
+    // the leaf still within its rate goes ahead of everyone borrowing,
+    // even though its parent is borrowing too
+    for (flow, leaf) in [within, borrowing, uncle].into_iter().enumerate() {
+        tree.enqueue(leaf, &packets(flow as u16)).unwrap();
+    }
+    let flows: Vec<_> = tree
+        .dequeue_batch(usize::MAX)
+        .iter()
+        .map(|packet| packet.flow)
+        .collect();
+    assert_eq!(flows[..5], [0; 5]);
+    assert_eq!(flows.len(), 15);
+}
+
+#[test]
+fn test_clock_stays_put_going_backwards() {
+    let clock = ManualClock::default();
+    clock.set(Duration::from_secs(2));
+    let backwards = clock.clone();
+    let result = std::panic::catch_unwind(|| backwards.set(Duration::from_secs(1)));
+    assert!(result.is_err());
+    assert_eq!(clock.now(), Duration::from_secs(2));
+}
@@ -0,0 +1,356 @@
+use std::time::Duration;
+
+use crate::queue::ClassQueue;
+use crate::shaper::TokenBucket;
+use crate::{
+    ClassMode, Clock, Config, DropPolicy, FlowMode, Mode, Packet, RateLimit, Round, SystemClock,
+};
+
+/// A node in a [`TreeScheduler`], only good for the scheduler that made it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NodeId(usize);
+
+/// Where a node stands with its rate and ceiling
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Standing {
+    /// Within its rate
+    CanSend,
+    /// Over its rate but within its ceiling
+    MayBorrow,
+    /// Over its ceiling
+    CantSend,
+}
+
+#[derive(Debug, Clone)]
+enum Kind {
+    /// Children share the node as the classes of a [`Scheduler`](crate::Scheduler)
+    /// share the link, in packets
+    Inner {
+        children: Vec<usize>,
+        config: Config,
+    },
+    Leaf(Box<ClassQueue>),
+}
+
+#[derive(Debug, Clone)]
+struct Node {
+    parent: Option<usize>,
+    kind: Kind,
+    /// What the node is guaranteed
+    rate: Option<TokenBucket>,
+    /// The most it can send, borrowing what its siblings leave unused
+    ceil: Option<TokenBucket>,
+}
+
+impl Node {
+    /// A bucket in debt holds the node back, a node with a rate and no
+    /// ceiling never borrows
+    fn standing(&self) -> Standing {
+        if self.ceil.as_ref().is_some_and(|ceil| !ceil.allows(0)) {
+            return Standing::CantSend;
+        }
+        match (&self.rate, &self.ceil) {
+            (None, _) => Standing::CanSend,
+            (Some(rate), _) if rate.allows(0) => Standing::CanSend,
+            (Some(_), Some(_)) => Standing::MayBorrow,
+            (Some(_), None) => Standing::CantSend,
+        }
+    }
+
+    /// When a node that can't send will be able to again
+    fn unblocked_at(&self) -> Option<Duration> {
+        if self.standing() != Standing::CantSend {
+            return None;
+        }
+        self.ceil.as_ref().or(self.rate.as_ref())?.eligible_at(0)
+    }
+}
+
+/// Whether anything under `node` can go out, letting leaves borrow if
+/// `borrow`. A leaf within its rate sends whether or not the nodes above it
+/// are within theirs, as long as none is over its ceiling.
+fn can_send(nodes: &[Node], node: usize, borrow: bool) -> bool {
+    let allowed = match (nodes[node].standing(), &nodes[node].kind) {
+        (Standing::CanSend, _) => true,
+        (Standing::MayBorrow, Kind::Inner { .. }) => true,
+        (Standing::MayBorrow, Kind::Leaf(_)) => borrow,
+        (Standing::CantSend, _) => false,
+    };
+    allowed
+        && match &nodes[node].kind {
+            Kind::Leaf(queue) => queue.len() > 0,
+            Kind::Inner { children, .. } => {
+                children.iter().any(|&child| can_send(nodes, child, borrow))
+            }
+        }
+}
+
+/// A tree of schedulers in the style of HTB. Leaves hold the packets, inner
+/// nodes share out what they send among their children by weight or
+/// priority, as a [`Scheduler`](crate::Scheduler) does its classes.
+///
+/// Any node can be given a rate, what it is guaranteed, and a ceiling, the
+/// most it can send. Leaves within their rate are served first, even if the
+/// nodes above them are over theirs, then those over it but under their
+/// ceiling share whatever capacity their siblings left unused. A node with
+/// a rate and no ceiling never goes over its rate. Every packet counts
+/// against the rate and ceiling of each node above it.
+#[derive(Debug, Clone)]
+pub struct TreeScheduler<C = SystemClock> {
+    /// The root is always the first
+    nodes: Vec<Node>,
+    /// Where each node's round robin over its children is up to
+    rounds: Vec<Round>,
+    clock: C,
+}
+
+impl Default for TreeScheduler {
+    fn default() -> Self {
+        Self::with_clock(SystemClock::default())
+    }
+}
+
+impl TreeScheduler {
+    /// Just the root, with no limits
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<C> TreeScheduler<C> {
+    pub fn with_clock(clock: C) -> Self {
+        let mut tree = Self {
+            nodes: Vec::new(),
+            rounds: Vec::new(),
+            clock,
+        };
+        tree.push_node(
+            None,
+            Kind::Inner {
+                children: Vec::new(),
+                config: empty_config(),
+            },
+        );
+        tree
+    }
+
+    pub fn root(&self) -> NodeId {
+        NodeId(0)
+    }
+
+    /// Adds a node for more nodes under `parent`, behind its other children
+    pub fn add_inner(&mut self, parent: NodeId, weight: usize) -> Result<NodeId, String> {
+        self.add_node(
+            parent,
+            weight,
+            Kind::Inner {
+                children: Vec::new(),
+                config: empty_config(),
+            },
+        )
+    }
+
+    /// Adds a node for packets under `parent`, behind its other children
+    pub fn add_leaf(&mut self, parent: NodeId, weight: usize) -> Result<NodeId, String> {
+        self.add_node(parent, weight, Kind::Leaf(Box::default()))
+    }
+
+    fn add_node(&mut self, parent: NodeId, weight: usize, kind: Kind) -> Result<NodeId, String> {
+        let node = self.nodes.len();
+        let Kind::Inner { children, config } = &mut self.nodes[parent.0].kind else {
+            return Err(format!("Node {} is a leaf", parent.0));
+        };
+        children.push(node);
+        config.weights.push(weight);
+        config.classes.push(ClassMode::default());
+        self.rounds[parent.0].credits.push(0);
+        self.push_node(Some(parent.0), kind);
+        Ok(NodeId(node))
+    }
+
+    fn push_node(&mut self, parent: Option<usize>, kind: Kind) {
+        self.nodes.push(Node {
+            parent,
+            kind,
+            rate: None,
+            ceil: None,
+        });
+        self.rounds.push(Round::new(&empty_config()));
+    }
+
+    /// The config of `node`'s parent, and where `node` is in it
+    fn sibling_config(&mut self, node: NodeId) -> (&mut Config, usize) {
+        let parent = self.nodes[node.0].parent.expect("the root has no siblings");
+        let Kind::Inner { children, config } = &mut self.nodes[parent].kind else {
+            unreachable!("parents aren't leaves")
+        };
+        let index = children
+            .iter()
+            .position(|&child| child == node.0)
+            .expect("nodes are children of their parent");
+        (config, index)
+    }
+
+    /// Panics for the root
+    pub fn set_weight(&mut self, node: NodeId, weight: usize) {
+        let (config, index) = self.sibling_config(node);
+        config.weights[index] = weight;
+    }
+
+    /// How `node` competes with its siblings, children that come later win
+    /// out among strict ones. Panics for the root.
+    pub fn set_class_mode(&mut self, node: NodeId, mode: ClassMode) {
+        let (config, index) = self.sibling_config(node);
+        config.classes[index] = mode;
+    }
+
+    fn leaf(&self, node: NodeId) -> &ClassQueue {
+        match &self.nodes[node.0].kind {
+            Kind::Leaf(queue) => queue,
+            Kind::Inner { .. } => panic!("node {} is not a leaf", node.0),
+        }
+    }
+
+    fn leaf_mut(&mut self, node: NodeId) -> &mut ClassQueue {
+        match &mut self.nodes[node.0].kind {
+            Kind::Leaf(queue) => queue,
+            Kind::Inner { .. } => panic!("node {} is not a leaf", node.0),
+        }
+    }
+
+    /// See [`Scheduler::set_flow_mode`](crate::Scheduler::set_flow_mode),
+    /// panics if `leaf` isn't one
+    pub fn set_flow_mode(&mut self, leaf: NodeId, mode: FlowMode) {
+        self.leaf_mut(leaf).set_mode(mode);
+    }
+
+    /// See [`Scheduler::set_capacity`](crate::Scheduler::set_capacity),
+    /// panics if `leaf` isn't one
+    pub fn set_capacity(&mut self, leaf: NodeId, capacity: Option<usize>) {
+        self.leaf_mut(leaf).set_capacity(capacity);
+    }
+
+    /// See [`Scheduler::set_drop_policy`](crate::Scheduler::set_drop_policy),
+    /// panics if `leaf` isn't one
+    pub fn set_drop_policy(&mut self, leaf: NodeId, policy: DropPolicy) {
+        self.leaf_mut(leaf).set_policy(policy);
+    }
+
+    /// Packets `leaf` has dropped so far, panics if it isn't one
+    pub fn drops(&self, leaf: NodeId) -> usize {
+        self.leaf(leaf).drops()
+    }
+
+    /// Packets waiting anywhere under `node`
+    pub fn queued(&self, node: NodeId) -> usize {
+        match &self.nodes[node.0].kind {
+            Kind::Leaf(queue) => queue.len(),
+            Kind::Inner { children, .. } => children
+                .iter()
+                .map(|&child| self.queued(NodeId(child)))
+                .sum(),
+        }
+    }
+
+    /// When, on the scheduler's clock, the first packet held back by a rate
+    /// or ceiling can go. None if no packet is held back by one.
+    pub fn next_eligible(&self) -> Option<Duration> {
+        (0..self.nodes.len())
+            .filter(|&node| matches!(&self.nodes[node].kind, Kind::Leaf(queue) if queue.len() > 0))
+            .filter_map(|leaf| {
+                let path = std::iter::successors(Some(leaf), |&node| self.nodes[node].parent);
+                path.filter_map(|node| self.nodes[node].unblocked_at())
+                    .max()
+            })
+            .min()
+    }
+
+    /// Picks a leaf with something to send, taking the turn at every node
+    /// on the way down
+    fn select(&mut self, borrow: bool) -> Option<usize> {
+        if !can_send(&self.nodes, 0, borrow) {
+            return None;
+        }
+        let mut node = 0;
+        loop {
+            let Kind::Inner { children, config } = &self.nodes[node].kind else {
+                return Some(node);
+            };
+            let nodes = &self.nodes;
+            let child = self.rounds[node].next_class(config, |index| {
+                can_send(nodes, children[index], borrow).then_some(1).into()
+            })?;
+            node = children[child];
+        }
+    }
+}
+
+impl<C: Clock> TreeScheduler<C> {
+    /// What `node` is guaranteed, every node starts out unlimited
+    pub fn set_rate(&mut self, node: NodeId, limit: Option<RateLimit>) {
+        let now = self.clock.now();
+        self.nodes[node.0].rate = limit.map(|limit| TokenBucket::new(limit, now));
+    }
+
+    /// The most `node` can send borrowing from its siblings
+    pub fn set_ceil(&mut self, node: NodeId, limit: Option<RateLimit>) {
+        let now = self.clock.now();
+        self.nodes[node.0].ceil = limit.map(|limit| TokenBucket::new(limit, now));
+    }
+
+    /// Returns the packets dropped to keep `leaf` within its capacity, which
+    /// may include ones queued earlier. The class of the packets doesn't
+    /// matter. Fails if `leaf` isn't one.
+    pub fn enqueue(&mut self, leaf: NodeId, packets: &[Packet]) -> Result<Vec<Packet>, String> {
+        let now = self.clock.now();
+        let Kind::Leaf(queue) = &mut self.nodes[leaf.0].kind else {
+            return Err(format!("Node {} is not a leaf", leaf.0));
+        };
+        Ok(packets
+            .iter()
+            .filter_map(|packet| queue.push(*packet, now))
+            .collect())
+    }
+
+    /// Takes the next packet out, see [`next_eligible`](TreeScheduler::next_eligible)
+    /// for when to try again if everything is held back by limits
+    pub fn dequeue(&mut self) -> Option<Packet> {
+        let now = self.clock.now();
+        for node in &mut self.nodes {
+            for bucket in node.rate.iter_mut().chain(&mut node.ceil) {
+                bucket.refill(now);
+            }
+        }
+        loop {
+            let leaf = self.select(false).or_else(|| self.select(true))?;
+            let Kind::Leaf(queue) = &mut self.nodes[leaf].kind else {
+                unreachable!("only leaves are selected")
+            };
+            // CoDel may drop everything left in the leaf on the way out
+            if let Some(packet) = queue.pop(now) {
+                let mut node = Some(leaf);
+                while let Some(current) = node {
+                    let current = &mut self.nodes[current];
+                    for bucket in current.rate.iter_mut().chain(&mut current.ceil) {
+                        bucket.spend(packet.length());
+                    }
+                    node = current.parent;
+                }
+                return Some(packet);
+            }
+        }
+    }
+
+    /// Takes up to `max` packets out, as many calls to [`dequeue`](Self::dequeue) would
+    pub fn dequeue_batch(&mut self, max: usize) -> Vec<Packet> {
+        std::iter::from_fn(|| self.dequeue()).take(max).collect()
+    }
+}
+
+fn empty_config() -> Config {
+    Config {
+        weights: Vec::new(),
+        mode: Mode::PacketCount,
+        classes: Vec::new(),
+    }
+}